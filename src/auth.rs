@@ -0,0 +1,131 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use bevy_renet::renet::transport::{ConnectToken, NETCODE_KEY_BYTES, NETCODE_USER_DATA_BYTES};
+
+// token issuer is a tiny http endpoint: GET /token?name=<player name> answers with ConnectToken bytes
+pub const TOKEN_PATH: &str = "/token";
+pub use crate::protocol::MAX_NAME_LEN;
+
+// user_data of the connect token is the player name, first byte is its length
+pub fn name_to_user_data(name: &str) -> [u8; NETCODE_USER_DATA_BYTES] {
+    let mut user_data = [0u8; NETCODE_USER_DATA_BYTES];
+    let name = clean_name(name);
+    user_data[0] = name.len() as u8;
+    user_data[1..=name.len()].copy_from_slice(name.as_bytes());
+    user_data
+}
+
+pub fn name_from_user_data(user_data: &[u8; NETCODE_USER_DATA_BYTES]) -> String {
+    let len = (user_data[0] as usize).min(MAX_NAME_LEN);
+    clean_name(&String::from_utf8_lossy(&user_data[1..=len]))
+}
+
+pub fn clean_name(name: &str) -> String {
+    crate::protocol::clean_text(name, MAX_NAME_LEN)
+}
+
+// private key in config files is written as 64 hex characters
+pub fn parse_private_key(hex: &str) -> Result<[u8; NETCODE_KEY_BYTES], String> {
+    let hex = hex.trim();
+    if hex.len() != NETCODE_KEY_BYTES * 2 {
+        return Err(format!("private key has to be {} hex characters", NETCODE_KEY_BYTES * 2));
+    }
+    let mut key = [0u8; NETCODE_KEY_BYTES];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| "private key has to be hex".to_string())?;
+    }
+    Ok(key)
+}
+
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// asks the token issuer of the server for a connect token, blocking, done once before connecting
+pub fn request_connect_token(issuer: SocketAddr, name: &str) -> io::Result<ConnectToken> {
+    let mut stream = TcpStream::connect_timeout(&issuer, Duration::from_secs(5))?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "GET {}?name={} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        TOKEN_PATH,
+        percent_encode(name),
+        issuer
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+
+    let header_end = response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid token response"))?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let status = head.lines().next().unwrap_or_default();
+    if !status.contains(" 200 ") {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("token issuer answered {}", status)));
+    }
+    ConnectToken::read(&mut &response[header_end + 4..])
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn name_survives_user_data() {
+        assert_eq!(name_from_user_data(&name_to_user_data("  Jakub  ")), "Jakub");
+        let long = "x".repeat(100);
+        assert_eq!(name_from_user_data(&name_to_user_data(&long)).len(), MAX_NAME_LEN);
+    }
+
+    #[test]
+    fn percent_coding_roundtrip() {
+        let name = "Žofia & spol";
+        assert_eq!(percent_decode(&percent_encode(name)), name);
+    }
+
+    #[test]
+    fn private_key_from_hex() {
+        let key = parse_private_key(&"0f".repeat(NETCODE_KEY_BYTES)).unwrap();
+        assert_eq!(key, [0x0f; NETCODE_KEY_BYTES]);
+        assert!(parse_private_key("abc").is_err());
+    }
+}
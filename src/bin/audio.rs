@@ -24,6 +24,3 @@ pub fn game_over_audio(asset_server: &Res<AssetServer>, audio: &Res<Audio>){
     let music = asset_server.load("game_over.ogg");
     audio.play(music);
 }
-
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
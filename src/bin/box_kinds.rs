@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use rand::Rng;
+use demo_bevy::level::Level;
+use demo_bevy::{BoxKind, ServerChannel, ServerMessages};
+use crate::collision_detection::{self, Collider};
+use crate::{Box, BoxState, BOX_SIZE};
+use crate::rooms::RoomServer;
+
+// how often the crane brings every kind, out of the sum of the weights
+const SPAWN_TABLE: [(BoxKind, u32); 5] = [
+    (BoxKind::Plain, 60),
+    (BoxKind::Heavy, 12),
+    (BoxKind::Bomb, 8),
+    (BoxKind::Ice, 10),
+    (BoxKind::Fragile, 10),
+];
+const HEAVY_PUSHERS: usize = 2;
+const BOMB_FUSE: Duration = Duration::from_secs(3); // from landing to the blast
+const BLAST_REACH: f32 = BOX_SIZE * 1.5; // the 8 cells around the bomb
+const ICE_SPEED: f32 = 150.0;
+
+pub fn roll_kind(rng: &mut impl Rng) -> BoxKind {
+    let total: u32 = SPAWN_TABLE.iter().map(|(_, weight)| weight).sum();
+    let mut roll = rng.gen_range(0..total);
+    for (kind, weight) in SPAWN_TABLE {
+        if roll < weight {
+            return kind;
+        }
+        roll -= weight;
+    }
+    BoxKind::Plain
+}
+
+// players pushing heavy boxes this tick, by box and direction (-1 left, 1 right)
+#[derive(Debug, Default)]
+pub struct HeavyPushes(HashMap<(Entity, i8), Vec<ClientId>>);
+
+impl HeavyPushes {
+    pub fn add(&mut self, entity: Entity, direction: i8, id: ClientId) {
+        self.0.entry((entity, direction)).or_default().push(id);
+    }
+
+    // boxes with enough players on one side, they move like a plain box and every pusher scores
+    pub fn moved(self) -> impl Iterator<Item = (Entity, i8, Vec<ClientId>)> {
+        self.0.into_iter()
+            .filter(|(_, pushers)| pushers.len() >= HEAVY_PUSHERS)
+            .map(|((entity, direction), pushers)| (entity, direction, pushers))
+    }
+}
+
+// lit when the bomb lands
+#[derive(Component)]
+pub struct Fuse(Timer);
+
+fn remove_box(commands: &mut Commands, server: &mut RoomServer, entity: Entity) {
+    let message = bincode::serialize(&ServerMessages::DespawnBox { entity_to_despawn: entity }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages, message);
+    commands.entity(entity).despawn();
+}
+
+#[allow(clippy::type_complexity)]
+pub fn explode_bombs(
+    mut commands: Commands,
+    mut server: RoomServer,
+    time: Res<Time>,
+    mut bombs: Query<(Entity, &Transform, &BoxKind, &BoxState, Option<&mut Fuse>), With<Box>>,
+    boxes: Query<(Entity, &Transform), With<Box>>,
+) {
+    let mut cleared = HashSet::new();
+    for (entity, transform, kind, box_state, fuse) in bombs.iter_mut() {
+        if *kind != BoxKind::Bomb || cleared.contains(&entity) {
+            continue;
+        }
+        let Some(mut fuse) = fuse else {
+            if box_state.oprety_zdola {
+                commands.entity(entity).insert(Fuse(Timer::new(BOMB_FUSE, TimerMode::Once)));
+            }
+            continue;
+        };
+        if !fuse.0.tick(time.delta()).finished() {
+            continue;
+        }
+        // the bomb goes too, boxes above fall on their own in handle_collisions
+        for (other, other_transform) in boxes.iter() {
+            let distance = (other_transform.translation - transform.translation).abs();
+            if distance.x < BLAST_REACH && distance.y < BLAST_REACH && cleared.insert(other) {
+                remove_box(&mut commands, &mut server, other);
+            }
+        }
+    }
+}
+
+// runs after handle_collisions, landed_on is only set for that tick
+pub fn break_fragile_boxes(
+    mut commands: Commands,
+    mut server: RoomServer,
+    boxes: Query<(Entity, &BoxKind, &BoxState), With<Box>>,
+) {
+    for (entity, kind, box_state) in boxes.iter() {
+        if *kind == BoxKind::Fragile && box_state.landed_on {
+            remove_box(&mut commands, &mut server, entity);
+        }
+    }
+}
+
+// pushed ice keeps going until a box or the last column stops it
+pub fn slide_ice(
+    time: Res<Time>,
+    level: Res<Level>,
+    mut boxy: Query<(Entity, &mut Transform, &Collider, &mut BoxState), With<Box>>,
+) {
+    let boxes: Vec<(Entity, Vec3, Collider)> = boxy.iter().map(|(entity, transform, collider, _)| (entity, transform.translation, *collider)).collect();
+    for (entity, mut transform, collider, mut box_state) in boxy.iter_mut() {
+        if box_state.slide == 0.0 {
+            continue;
+        }
+        if !box_state.oprety_zdola {
+            box_state.slide = 0.0; // slid off the stack, falls straight down
+            continue;
+        }
+        let mut next = transform.translation;
+        next.x = (next.x + box_state.slide * ICE_SPEED * time.delta_seconds()).clamp(level.leftmost_column(), level.rightmost_column());
+        // same rule as handle_collisions, shallower in x than in y is a hit from the side
+        let blocked = boxes.iter().any(|(other, translation, other_collider)| {
+            *other != entity
+                && collision_detection::test_aabb_overlap(collider.aabb(next), other_collider.aabb(*translation))
+                    .is_some_and(|(depth_x, depth_y)| depth_x.abs() < depth_y.abs())
+        });
+        if blocked || next.x == transform.translation.x {
+            box_state.slide = 0.0;
+            continue;
+        }
+        transform.translation = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use crate::BOX_COLLIDER;
+    use bevy_renet::renet::RenetServer;
+    use demo_bevy::connection_config;
+    use rand::SeedableRng;
+
+    #[test]
+    fn every_kind_spawns_and_heavy_needs_two() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let kinds: HashSet<BoxKind> = (0..500).map(|_| roll_kind(&mut rng)).collect();
+        assert_eq!(kinds.len(), SPAWN_TABLE.len());
+
+        let (heavy, other) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut pushes = HeavyPushes::default();
+        pushes.add(heavy, 1, ClientId::from_raw(1));
+        pushes.add(heavy, 1, ClientId::from_raw(2));
+        pushes.add(other, 1, ClientId::from_raw(1));
+        pushes.add(other, -1, ClientId::from_raw(2));
+        let moved: Vec<_> = pushes.moved().collect();
+        assert_eq!(moved.len(), 1);
+        assert_eq!((moved[0].0, moved[0].1), (heavy, 1));
+    }
+
+    #[test]
+    fn bomb_clears_its_neighbours_after_the_fuse() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(RenetServer::new(connection_config()))
+            .init_resource::<RoomMembers>()
+            .add_systems(Update, explode_bombs);
+        let settled = || BoxState { oprety_zdola: true, ..default() };
+        let bomb = app.world.spawn((Transform::from_xyz(0.0, 32.0, 2.0), Box, BoxKind::Bomb, settled())).id();
+        let next = app.world.spawn((Transform::from_xyz(70.0, 32.0, 2.0), Box, BoxKind::Plain, settled())).id();
+        let above = app.world.spawn((Transform::from_xyz(0.0, 103.0, 2.0), Box, BoxKind::Ice, settled())).id();
+        let far = app.world.spawn((Transform::from_xyz(140.0, 32.0, 2.0), Box, BoxKind::Plain, settled())).id();
+
+        app.update(); // lands, fuse is lit
+        app.world.resource_mut::<Time>().advance_by(BOMB_FUSE - Duration::from_millis(100));
+        app.update();
+        assert!(app.world.get_entity(next).is_some());
+        app.world.resource_mut::<Time>().advance_by(Duration::from_millis(200));
+        app.update();
+        for entity in [bomb, next, above] {
+            assert!(app.world.get_entity(entity).is_none());
+        }
+        assert!(app.world.get_entity(far).is_some());
+    }
+
+    #[test]
+    fn ice_slides_until_it_hits_a_box() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<Level>()
+            .add_systems(Update, slide_ice);
+        let ice = app.world.spawn((Transform::from_xyz(-360.0, 32.0, 2.0), BOX_COLLIDER, Box,
+                                   BoxState { oprety_zdola: true, slide: 1.0, ..default() })).id();
+        app.world.spawn((Transform::from_xyz(-80.0, 32.0, 2.0), BOX_COLLIDER, Box, BoxState { oprety_zdola: true, ..default() }));
+        for _ in 0..200 {
+            app.world.resource_mut::<Time>().advance_by(Duration::from_millis(16));
+            app.update();
+        }
+        let x = app.world.get::<Transform>(ice).unwrap().translation.x;
+        assert!(x > -160.0 && x < -80.0 - BOX_SIZE + 10.0, "{}", x);
+        assert_eq!(app.world.get::<BoxState>(ice).unwrap().slide, 0.0);
+    }
+}
@@ -9,6 +9,3 @@ pub fn setup_camera(mut commands: Commands) {
         // tu bol marker (struct) aby sa dala vyhladavat ale nehybeme nou tak netreba
     ));
 }
-
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
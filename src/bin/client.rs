@@ -3,15 +3,17 @@ use bevy_kira_audio::prelude::*;
 use bevy::{
     prelude::*,
 };
-use bevy::app::AppExit;
-use bevy::ecs::query::QueryData;
 use bevy_renet::{
     client_connected,
     renet::{ClientId, RenetClient},
     RenetClientPlugin,
 };
 use sprite_animation::{AnimationIndices, AnimationTimer, Player};
-use demo_bevy::{connection_config, setup_level, NetworkedEntities, NetworkedBoxes, PlayerInput, ServerChannel, ServerMessages, ReadyText};
+use demo_bevy::{setup_level, spawn_ready_text, PlayerInput, RunState, ServerChannel, ServerMessages, ReadyText};
+use demo_bevy::protocol::decode;
+use demo_bevy::level::{spawn_level_art, Level};
+use demo_bevy::movement::Animation;
+use interpolation::SnapshotBuffer;
 
 
 // MODS
@@ -20,9 +22,11 @@ mod game_over;
 mod sprite_animation;
 mod camera_setup;
 mod player_input;
-
-// CONST
-const FLOOR_HEIGHT: f32 = 35.0;
+mod interpolation;
+mod prediction;
+mod connection;
+mod lobby;
+mod power_ups;
 
 // COMPONENTS
 #[derive(Component)]
@@ -30,14 +34,11 @@ struct ControlledPlayer;
 #[derive(Default, Resource)]
 struct NetworkMapping(HashMap<Entity, Entity>);
 
-#[derive(Component)]
-struct ExitTimer {
-    timer: Timer,
-}
 #[derive(Debug)]
 struct PlayerInfo {
     client_entity: Entity,
     server_entity: Entity,
+    name: String,
 }
 
 #[derive(Debug, Default, Resource)]
@@ -59,46 +60,88 @@ struct Ready;
 // CONNECT TO SERVER
 #[cfg(feature = "transport")]
 fn add_netcode_network(app: &mut App) {
-    use bevy_renet::renet::transport::{ClientAuthentication, NetcodeClientTransport, NetcodeTransportError};
-    use demo_bevy::PROTOCOL_ID;
-    use std::{net::UdpSocket, time::SystemTime};
+    use demo_bevy::settings::ClientSettings;
+    use connection::ConnectionState;
 
     app.add_plugins(bevy_renet::transport::NetcodeClientPlugin);
 
-    app.configure_sets(Update, Connected.run_if(client_connected));
-
-    let client = RenetClient::new(connection_config());
-    let server_addr = "127.0.0.1:5000".parse().unwrap();
-    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
-    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-    let client_id = current_time.as_millis() as u64;
-    let authentication = ClientAuthentication::Unsecure {
-        client_id,
-        protocol_id: PROTOCOL_ID,
-        server_addr,
-        user_data: None,
-    };
-
-    let transport = NetcodeClientTransport::new(current_time, authentication, socket).unwrap();
-
-    app.insert_resource(client);
-    app.insert_resource(transport);
-    app.insert_resource(CurrentClientId(client_id));
-
-    // If any error is found we just panic
-    #[allow(clippy::never_loop)]
-    fn panic_on_error_system(mut renet_error: EventReader<NetcodeTransportError>) {
-        for e in renet_error.read() {
-            panic!("{}", e);
+    app.configure_sets(Update, Connected.run_if(client_connected.and_then(connection::welcomed)));
+    app.configure_sets(FixedUpdate, Connected.run_if(client_connected.and_then(connection::welcomed)));
+    app.add_systems(Update, (connection::send_hello, connection::receive_handshake_reply).run_if(client_connected));
+
+    let settings = ClientSettings::from_args();
+    app.insert_resource(connection::RoomCode(settings.room.clone()));
+    // a server that is down is not a reason to crash, the connection lost screen lets the player retry
+    match connection::connect(&settings) {
+        Ok((client, transport, client_id)) => {
+            app.insert_resource(client);
+            app.insert_resource(transport);
+            app.insert_resource(CurrentClientId(client_id));
+            app.init_state::<ConnectionState>();
+        }
+        Err(reason) => {
+            app.insert_resource(CurrentClientId(0));
+            app.insert_resource(connection::LostReason(reason));
+            app.insert_state(ConnectionState::Lost);
         }
     }
+    // inputs are sent and predicted on the same tick the server simulates them
+    app.insert_resource(Time::<Fixed>::from_hz(settings.tick_rate));
+    app.insert_resource(interpolation::InterpolationConfig {
+        tick_rate: settings.tick_rate,
+        delay: settings.interpolation_delay_ms as f64 / 1000.0,
+        max_extrapolation: settings.max_extrapolation_ms as f64 / 1000.0,
+    });
+    app.insert_resource(settings);
+
+    app.add_event::<connection::ConnectionLost>();
+    app.add_systems(
+        Update,
+        (connection::transport_errors, connection::lose_connection)
+            .chain()
+            .after(Connected)
+            .run_if(in_state(ConnectionState::Online)),
+    );
+    app.add_systems(OnEnter(ConnectionState::Lost), (reset_session, connection::spawn_connection_lost_screen));
+    app.add_systems(OnExit(ConnectionState::Lost), (connection::despawn_connection_lost_screen, spawn_ready_text_again));
+    app.add_systems(Update, connection::connection_lost_menu.run_if(in_state(ConnectionState::Lost)));
+}
 
-    app.add_systems(Update, panic_on_error_system);
+// forget everything about the last connection, connecting again starts from scratch
+#[allow(clippy::type_complexity)]
+fn reset_session(
+    mut commands: Commands,
+    mut lobby: ResMut<ClientLobby>,
+    mut network_mapping: ResMut<NetworkMapping>,
+    session_entities: Query<Entity, Or<(With<Ready>, With<game_over::Dead>, With<game_over::GameOverScreen>)>>,
+) {
+    for entity in network_mapping.0.values() {
+        commands.entity(*entity).despawn();
+    }
+    for entity in session_entities.iter() {
+        commands.entity(entity).despawn();
+    }
+    lobby.players.clear();
+    network_mapping.0.clear();
+    commands.insert_resource(connection::Handshake::default());
+    commands.insert_resource(prediction::Prediction::default());
+    commands.insert_resource(interpolation::ReceivedSnapshots::default());
+    commands.insert_resource(interpolation::RenderClock::default());
+    commands.insert_resource(Score::default());
+    commands.insert_resource(lobby::ChatInput::default());
+    commands.insert_resource(lobby::Spectator::default());
+    commands.insert_resource(power_ups::PowerUps::default());
+}
+
+fn spawn_ready_text_again(mut commands: Commands, ready_text: Query<Entity, With<ReadyText>>) {
+    if ready_text.is_empty() {
+        spawn_ready_text(&mut commands);
+    }
 }
 
 // GET SERVER MESSAGES AND DO STUFF WITH THEM ALSO GET ALL THE ENTITIES
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn client_sync_players(
-    mut exit: EventWriter<AppExit>,
     audio: Res<Audio>,
     mut commands: Commands,
     mut client: ResMut<RenetClient>,
@@ -107,18 +150,49 @@ fn client_sync_players(
     mut network_mapping: ResMut<NetworkMapping>,
     asset_server: Res<AssetServer>,
     mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
-    mut query: Query<(&mut Transform,&AnimationIndices, &mut AnimationTimer, &mut TextureAtlas, &mut Player)>,
-    ready_text: Query<Entity, With<ReadyText>>,
-    ready: Query<Entity, With<Ready>>,
-    dead: Query<Entity, With<game_over::Dead>>,
-    //mut query_score: Query<TextBundle, With<ScoreText>>,
+    (ready_text, ready, dead, game_over_screen, game_over_text): (
+        Query<Entity, With<ReadyText>>,
+        Query<Entity, With<Ready>>,
+        Query<Entity, With<game_over::Dead>>,
+        Query<Entity, With<game_over::GameOverScreen>>,
+        Query<Entity, With<game_over::GameOverText>>,
+    ),
+    mut score: ResMut<Score>,
+    mut prediction: ResMut<prediction::Prediction>,
+    mut chat_log: ResMut<lobby::ChatLog>,
+    mut spectator: ResMut<lobby::Spectator>,
+    (mut room, mut level, mut power_ups): (ResMut<connection::RoomCode>, ResMut<Level>, ResMut<power_ups::PowerUps>),
+    mut lost: EventWriter<connection::ConnectionLost>,
 ) {
     let client_id = client_id.0;
     while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
-        let server_message = bincode::deserialize(&message).unwrap();
+        let server_message = match decode(&message) {
+            Ok(server_message) => server_message,
+            Err(e) => {
+                lost.send(connection::ConnectionLost { reason: format!("Server sent a message we can not read: {}", e) });
+                return;
+            }
+        };
         match server_message {
+            ServerMessages::Room{ code, level: level_id } => {
+                chat_log.push(format!("Room {}, friends join with --room {}", code, code));
+                room.0 = code;
+                if level.id != level_id {
+                    match Level::load(&level_id) {
+                        Ok(loaded) => *level = loaded,
+                        Err(e) => {
+                            lost.send(connection::ConnectionLost { reason: format!("Server plays a level we do not have, update the game ({})", e) });
+                            return;
+                        }
+                    }
+                }
+            }
             // pridaj hraca
-            ServerMessages::PlayerCreate { id, translation: _, entity, asset } => {
+            ServerMessages::PlayerCreate { id, translation, entity, asset, name } => {
+                // can come twice when somebody joins while we wait for the welcome
+                if lobby.players.contains_key(&id) {
+                    continue;
+                }
                 println!("Player {} connected.", id);
                 let texture = asset_server.load(asset);
                 let layout = TextureAtlasLayout::from_grid(Vec2::new(24.0,24.0), 7, 1, None, None);
@@ -128,7 +202,7 @@ fn client_sync_players(
 
                 let mut client_entity = commands.spawn((
                     SpriteBundle {
-                        transform: Transform::from_translation(Vec3::new(100.0, FLOOR_HEIGHT, 2.0)) // transform move sprite
+                        transform: Transform::from_translation(translation.into()) // transform move sprite
                             * Transform::from_scale(Vec3::splat(4.0)), // scale sprite
                         texture,
                         ..default()
@@ -138,21 +212,25 @@ fn client_sync_players(
                         index: animation_indices.first,
                     },
                     Player{
-                        id,
-                        should_animate: false,
-                        should_invert: false
+                        animation: Animation::Idle,
                     },
                     animation_indices,
                     AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
-                    // PlayerState::default(),
-                    // JumpState::default(), // Add JumpState component with default values
                 ));
+                // vlastny hrac je predikovany, ostatni su interpolovani
                 if client_id == id.raw() {
                     client_entity.insert(ControlledPlayer);
+                    // a spectator got its place
+                    if spectator.watching {
+                        *spectator = lobby::Spectator::default();
+                    }
+                } else {
+                    client_entity.insert(SnapshotBuffer::default());
                 }
                 let player_info = PlayerInfo {
                     server_entity: entity,
                     client_entity: client_entity.id(),
+                    name,
                 };
                 lobby.players.insert(id, player_info);
                 network_mapping.0.insert(entity, client_entity.id());
@@ -163,23 +241,54 @@ fn client_sync_players(
                 if let Some(PlayerInfo {
                     server_entity,
                     client_entity,
+                    ..
                 }) = lobby.players.remove(&id)
                 {
                     commands.entity(client_entity).despawn();
                     network_mapping.0.remove(&server_entity);
                 }
             }
-            ServerMessages::SpawnBox { entity,translation } => {
-                let novy_box = commands.spawn(SpriteBundle{
+            ServerMessages::SpawnBox { entity,translation, kind } => {
+                // the join sync can repeat a box we already got while joining
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+                let novy_box = commands.spawn((SpriteBundle{
                     transform: Transform::from_translation(translation.into()),
-                    texture: asset_server.load("box2.png"),
+                    texture: asset_server.load(kind.sprite()),
                     ..default()
-                });
+                }, SnapshotBuffer::default()));
                 network_mapping.0.insert(entity, novy_box.id());
-                //update_score(&score);
-                //update_score_text(&score, &query_score);
                 }
 
+            ServerMessages::SpawnPickup { entity, translation, kind } => {
+                if network_mapping.0.contains_key(&entity) {
+                    continue;
+                }
+                let pickup = commands.spawn((SpriteBundle{
+                    transform: Transform::from_translation(translation.into()) * Transform::from_scale(Vec3::splat(2.0)),
+                    texture: asset_server.load(kind.sprite()),
+                    ..default()
+                }, SnapshotBuffer::default()));
+                network_mapping.0.insert(entity, pickup.id());
+            }
+            ServerMessages::PowerUps { timed } => {
+                power_ups.timed = timed;
+            }
+            ServerMessages::Lives { id, lives } => {
+                if id.raw() == client_id {
+                    if lives < power_ups.lives && lives > 0 {
+                        chat_log.push(format!("You lost a life, {} left", lives));
+                    }
+                    power_ups.lives = lives;
+                }
+                score.lives.insert(id, lives);
+            }
+            ServerMessages::Invulnerable { id, seconds } => {
+                if let Some(player) = lobby.players.get(&id) {
+                    commands.entity(player.client_entity).insert(sprite_animation::Blink(Timer::from_seconds(seconds, TimerMode::Once)));
+                }
+            }
             ServerMessages::DespawnBox{entity_to_despawn} => {
                 // bez toho Some to robilo zle veci
                 if let Some(entity) = network_mapping.0.remove(&entity_to_despawn) {
@@ -187,89 +296,123 @@ fn client_sync_players(
                 }
 
             }
-            // start animating player
-            ServerMessages::AnimatePlayer{ id, facing_right: _} => {
-                sprite_animation::turn_on_animate(id,&mut query);
-                // flip_player(id, facing_right, &mut query);
-            }
-            // stop animating player
-            ServerMessages::StopAnimate{ id} => {
-                sprite_animation::turn_off_animate(id,&mut query);
-            }
-            ServerMessages::ExitWindow{} => {
-                game_over::exit_game(&mut exit)
-            }
             ServerMessages::YouReady{id } => {
                if id.raw() == client_id{
                     spawn_ready(&mut commands)
                }
             }
+            ServerMessages::NotReady{id } => {
+                if id.raw() == client_id{
+                    for entity in ready.iter() {
+                        commands.entity(entity).despawn();
+                    }
+                }
+            }
+            ServerMessages::PlayerRenamed{ id, name } => {
+                if let Some(player) = lobby.players.get_mut(&id) {
+                    chat_log.push(format!("{} is now {}", player_name(&player.name, id), name));
+                    player.name = name;
+                }
+            }
+            ServerMessages::PlayerSkin{ id, asset } => {
+                if let Some(player) = lobby.players.get(&id) {
+                    commands.entity(player.client_entity).insert(asset_server.load::<Image>(asset));
+                }
+            }
+            ServerMessages::Difficulty{ difficulty } => {
+                chat_log.push(format!("Difficulty {:?}", difficulty));
+            }
+            ServerMessages::Chat{ id, name, text } => {
+                chat_log.push(format!("{}: {}", player_name(&name, id), text));
+            }
+            ServerMessages::Spectating{ queued } => {
+                if queued {
+                    chat_log.push("You play in the next round".to_string());
+                }
+                *spectator = lobby::Spectator { watching: true, queued };
+            }
+            ServerMessages::RestartVotes{ votes, needed } => {
+                chat_log.push(format!("Restart votes {}/{}, press V to vote", votes, needed));
+            }
+            ServerMessages::RoundRestart{} => {
+                prediction.running = false;
+                // results stay on screen until everybody is ready
+                for entity in ready.iter().chain(dead.iter()).chain(game_over_text.iter()) {
+                    commands.entity(entity).despawn();
+                }
+                if ready_text.is_empty() {
+                    spawn_ready_text(&mut commands);
+                }
+                score.dead.clear();
+                chat_log.push("Round restarted".to_string());
+            }
             ServerMessages::AllReady{} => {
+                prediction.running = true;
                 for entity in ready_text.iter() {
                     commands.entity(entity).despawn();
                 }
-                for entity in ready.iter() {
+                for entity in ready.iter().chain(game_over_screen.iter()) {
                     commands.entity(entity).despawn();
                 }
             }
+            ServerMessages::RoundOver{ team, mut players } => {
+                players.sort_by_key(|(id, _)| id.raw());
+                score.team = team;
+                score.players = players;
+                let results = format!("Score : {}{}", score.team, score_lines(&score, client_id, &lobby));
+                game_over::game_over_spawn(&mut commands, &asset_server, &dead, results);
+            }
             ServerMessages::YouDead{id } => {
                 if id.raw() == client_id{
                     spawn_dead(&mut commands)
                 }
+                if !score.dead.contains(&id) {
+                    score.dead.push(id);
+                }
+            }
+            // we joined a match that is already going
+            ServerMessages::JoinSync{ state } => {
+                if state != RunState::Waiting {
+                    for entity in ready_text.iter().chain(ready.iter()) {
+                        commands.entity(entity).despawn();
+                    }
+                }
+                match state {
+                    RunState::Waiting => {}
+                    RunState::Playing => prediction.running = true,
+                    RunState::GameOver => {
+                        let results = format!("Score : {}{}", score.team, score_lines(&score, client_id, &lobby));
+                        game_over::game_over_spawn(&mut commands, &asset_server, &dead, results)
+                    }
+                }
+            }
+            ServerMessages::ScoreUpdate{ team, mut players } => {
+                players.sort_by_key(|(id, _)| id.raw());
+                score.team = team;
+                score.players = players;
             }
             // to play sound
             ServerMessages::SoundAction{ sound } => {
                 match sound {
                     1 => audio::jump_audio(&asset_server,&audio),
                     2 => audio::fall_box_audio(&asset_server,&audio),
-                    3 => {audio::game_over_audio(&asset_server,&audio);
-                        game_over::game_over_spawn(&mut commands, &asset_server, &dead);
-                    },
+                    3 => audio::game_over_audio(&asset_server,&audio),
                     4 => audio::death_audio(&asset_server,&audio),
                     _ => { },
                 }
             }
             }
         }
-
-    // berie od serveru pozicie entit
-    while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
-        let networked_entities: NetworkedEntities = bincode::deserialize(&message).unwrap();
-
-        for i in 0..networked_entities.entities.len() {
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
-                let scale = Vec3::new(networked_entities.scales[i], 4.0, 4.0);
-                let transform = Transform {
-                    translation,
-                    rotation: Default::default(),
-                    scale
-                };
-
-                commands.entity(*entity).insert(transform);
-            }
-        }
-    }
-    // cita channel networkedBoxes
-    while let Some(message) = client.receive_message(ServerChannel::NetworkedBoxes) {
-        let networked_entities: NetworkedBoxes = bincode::deserialize(&message).unwrap();
-
-        for i in 0..networked_entities.entities.len() {
-            if let Some(entity) = network_mapping.0.get(&networked_entities.entities[i]) {
-                let translation = networked_entities.translations[i].into();
-                let k = Vec3::splat(1.0);
-                let transform = Transform {
-                    translation,
-                    rotation: Default::default(),
-                    scale: k,
-                };
-
-                commands.entity(*entity).insert(transform);
-            }
-        }
+}
+// players without a name from the connect token
+fn player_name(name: &str, id: ClientId) -> String {
+    if name.is_empty() {
+        format!("Player {}", id)
+    } else {
+        name.to_string()
     }
 }
-pub fn spawn_ready(mut commands: &mut Commands){
+pub fn spawn_ready(commands: &mut Commands){
     commands.spawn((TextBundle::from_sections([TextSection::new(
         "Ready",
         TextStyle {
@@ -284,7 +427,7 @@ pub fn spawn_ready(mut commands: &mut Commands){
         ..default()
     }),Ready));
 }
-pub fn spawn_dead(mut commands: &mut Commands){
+pub fn spawn_dead(commands: &mut Commands){
     commands.spawn((TextBundle::from_sections([TextSection::new(
         "YOU ARE DEAD",
         TextStyle {
@@ -299,30 +442,68 @@ pub fn spawn_dead(mut commands: &mut Commands){
         ..default()
     }),game_over::Dead));
 }
-#[derive(Debug, Clone, PartialEq, Eq, Resource)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Resource)]
 pub struct Score {
-    pub score: u32,
+    pub team: u32,
+    pub players: Vec<(ClientId, u32)>, // sorted by id so players keep their place in the panel
+    pub dead: Vec<ClientId>,
+    pub lives: HashMap<ClientId, u32>,
 }
 
-
-
-#[derive(Component, QueryData)]
+#[derive(Component)]
 pub struct ScoreText;
-fn spawn_score(mut commands: &mut Commands){
-    commands.spawn((TextBundle::from_sections([TextSection::new(
-        "Score : 0",
-        TextStyle {
-            font_size: 40.0,
-            color: Color::rgb(0.0, 0.0, 0.0),
-            ..default()
-        },
-    )]).with_style(Style {
+pub fn spawn_score(mut commands: Commands){
+    commands.spawn((TextBundle::from_sections([
+        TextSection::new(
+            "Score : 0",
+            TextStyle {
+                font_size: 40.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+        TextSection::new(
+            "",
+            TextStyle {
+                font_size: 25.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+    ]).with_style(Style {
         position_type: PositionType::Absolute,
-        bottom: Val::Px(600.0),
-        left: Val::Px(100.0),
+        top: Val::Px(20.0),
+        left: Val::Px(20.0),
         ..default()
     }),ScoreText));
 }
+// redraw score panel when server sends new score
+fn update_score_text(score: Res<Score>, client_id: Res<CurrentClientId>, lobby: Res<ClientLobby>, mut query: Query<&mut Text, With<ScoreText>>){
+    for mut text in query.iter_mut() {
+        text.sections[0].value = format!("Score : {}", score.team);
+        text.sections[1].value = score_lines(&score, client_id.0, &lobby);
+    }
+}
+// one line for every player, the score panel and the results screen
+fn score_lines(score: &Score, client_id: u64, lobby: &ClientLobby) -> String {
+    score.players.iter().enumerate()
+        .map(|(i, (id, points))| {
+            let dead = match score.lives.get(id) {
+                _ if score.dead.contains(id) => " (dead)".to_string(),
+                Some(lives) => format!(" (lives {})", lives),
+                None => String::new(),
+            };
+            if id.raw() == client_id {
+                format!("\nYou : {}{}", points, dead)
+            } else {
+                match lobby.players.get(id).filter(|player| !player.name.is_empty()) {
+                    Some(player) => format!("\n{} : {}{}", player.name, points, dead),
+                    None => format!("\nPlayer {} : {}{}", i + 1, points, dead),
+                }
+            }
+        })
+        .collect()
+}
 // Main WINDOW CREATION AND APP
 fn main() {
     let mut app = App::new();
@@ -338,22 +519,47 @@ fn main() {
         }));
     app.add_plugins(RenetClientPlugin);
     app.add_plugins(AudioPlugin);
-    //app.init_resource::<Score>();
+    app.init_resource::<Score>();
     #[cfg(feature = "transport")]
     add_netcode_network(&mut app);
 
 
     app.insert_resource(ClientLobby::default());
     app.insert_resource(PlayerInput::default());
+    app.init_resource::<prediction::Prediction>();
+    app.init_resource::<connection::Handshake>();
+    app.init_resource::<connection::PlayerKey>();
+    app.init_resource::<connection::RoomCode>();
+    app.init_resource::<Level>();
     app.insert_resource(NetworkMapping::default());
+    app.init_resource::<interpolation::InterpolationConfig>();
+    app.init_resource::<interpolation::RenderClock>();
+    app.init_resource::<interpolation::ReceivedSnapshots>();
+    app.init_resource::<lobby::ChatLog>();
+    app.init_resource::<lobby::ChatInput>();
+    app.init_resource::<lobby::Spectator>();
+    app.init_resource::<power_ups::PowerUps>();
     app.add_systems(Update, player_input::player_input);
     app.add_systems(
         Update,
-        (player_input::client_send_input, client_sync_players).in_set(Connected),
+        (client_sync_players, interpolation::receive_snapshots, prediction::receive_input_ack).in_set(Connected),
     );
+    app.add_systems(Update, (lobby::lobby_keys, lobby::chat_input).chain().in_set(Connected));
+    app.add_systems(
+        FixedUpdate,
+        (player_input::client_send_input, prediction::predict_local_player).chain().in_set(Connected).run_if(lobby::has_player),
+    );
+    app.add_systems(Update, interpolation::interpolate_snapshots.after(interpolation::receive_snapshots));
     app.add_systems(FixedUpdate, sprite_animation::animate_sprite);
-
-    app.add_systems(Startup, (setup_level, camera_setup::setup_camera, audio::main_music_audio));
+    app.add_systems(Update, sprite_animation::blink);
+    app.add_systems(Update, update_score_text.run_if(resource_changed::<Score>.or_else(resource_changed::<ClientLobby>)));
+    app.add_systems(Update, lobby::update_spectator_text.run_if(resource_changed::<lobby::Spectator>));
+    app.add_systems(Update, lobby::hide_ready_text);
+    app.add_systems(Update, spawn_level_art.run_if(resource_changed::<Level>));
+    app.add_systems(Update, (power_ups::update_power_up_hud.run_if(resource_changed::<power_ups::PowerUps>), power_ups::count_down_power_ups).chain());
+    app.add_systems(Update, lobby::update_chat_text.run_if(resource_changed::<lobby::ChatLog>.or_else(resource_changed::<lobby::ChatInput>)));
+
+    app.add_systems(Startup, (setup_level, camera_setup::setup_camera, audio::main_music_audio, spawn_score, lobby::spawn_chat, power_ups::spawn_power_up_hud));
 
     app.run();
 }
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use demo_bevy::protocol::{clean_text, decode, MAX_CHAT_LEN, MAX_NAME_LEN};
+use demo_bevy::{ClientChannel, ClientCommand, Player, ServerChannel, ServerMessages, SKINS};
+use crate::round::RestartRound;
+use crate::director::Director;
+use crate::violations::ProtocolViolation;
+use crate::{PlayerState, RunState, ServerLobby};
+use crate::rooms::RoomServer;
+
+// players that want the round restarted
+#[derive(Resource, Debug, Default)]
+pub struct RestartVotes {
+    voters: HashSet<ClientId>,
+}
+
+impl RestartVotes {
+    pub fn clear(&mut self) {
+        self.voters.clear();
+    }
+}
+
+// more than half of the players
+fn votes_needed(players: usize) -> u32 {
+    players as u32 / 2 + 1
+}
+
+// hello was read by the handshake, everything else a welcomed client says on ClientChannel::Command
+#[allow(clippy::too_many_arguments)]
+pub fn receive_client_commands(
+    mut server: RoomServer,
+    lobby: Res<ServerLobby>,
+    run_state: Res<State<RunState>>,
+    mut players: Query<(&mut Player, &mut PlayerState)>,
+    mut votes: ResMut<RestartVotes>,
+    mut director: ResMut<Director>,
+    mut restart: EventWriter<RestartRound>,
+    mut violations: EventWriter<ProtocolViolation>,
+) {
+    votes.voters.retain(|client_id| lobby.players.contains_key(client_id));
+    let waiting = *run_state.get() == RunState::Waiting;
+    for (client_id, entity) in lobby.players.iter() {
+        let client_id = *client_id;
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
+            let command = match decode::<ClientCommand>(&message) {
+                Ok(command) => command,
+                Err(error) => {
+                    violations.send(ProtocolViolation { client_id, channel: "command", error });
+                    continue;
+                }
+            };
+            let Ok((mut player, mut player_state)) = players.get_mut(*entity) else {
+                continue;
+            };
+            let reply = match command {
+                ClientCommand::Hello { .. } => None, // already welcomed
+                ClientCommand::Ready if waiting => {
+                    player_state.ready = true;
+                    Some(ServerMessages::YouReady { id: client_id })
+                }
+                ClientCommand::Unready if waiting => {
+                    player_state.ready = false;
+                    Some(ServerMessages::NotReady { id: client_id })
+                }
+                ClientCommand::Ready | ClientCommand::Unready => None,
+                ClientCommand::SetName { name } => {
+                    let name = clean_text(&name, MAX_NAME_LEN);
+                    if name.is_empty() {
+                        continue;
+                    }
+                    println!("Player {} ({}) is now {}", client_id, player.name, name);
+                    player.name = name.clone();
+                    Some(ServerMessages::PlayerRenamed { id: client_id, name })
+                }
+                ClientCommand::PickSkin { skin } if waiting => {
+                    SKINS.get(skin as usize).map(|asset| {
+                        player.asset = asset.to_string();
+                        ServerMessages::PlayerSkin { id: client_id, asset: player.asset.clone() }
+                    })
+                }
+                ClientCommand::PickSkin { .. } => None,
+                // anybody in the waiting room can change it for the whole room
+                ClientCommand::PickDifficulty { difficulty } if waiting => {
+                    println!("Player {} ({}) picked {:?}", client_id, player.name, difficulty);
+                    director.difficulty = difficulty;
+                    Some(ServerMessages::Difficulty { difficulty })
+                }
+                ClientCommand::PickDifficulty { .. } => None,
+                ClientCommand::Chat { text } => {
+                    let text = clean_text(&text, MAX_CHAT_LEN);
+                    if text.is_empty() {
+                        continue;
+                    }
+                    println!("{}: {}", player.name, text);
+                    Some(ServerMessages::Chat { id: client_id, name: player.name.clone(), text })
+                }
+                // nothing to restart before the match started
+                ClientCommand::VoteRestart if !waiting => {
+                    votes.voters.insert(client_id);
+                    let needed = votes_needed(lobby.players.len());
+                    let count = votes.voters.len() as u32;
+                    if count >= needed {
+                        votes.voters.clear();
+                        restart.send(RestartRound);
+                    }
+                    Some(ServerMessages::RestartVotes { votes: count, needed })
+                }
+                ClientCommand::VoteRestart => None,
+                ClientCommand::JoinNextRound => None, // already playing
+                ClientCommand::Leave => {
+                    println!("Player {} left.", client_id);
+                    server.disconnect(client_id);
+                    break;
+                }
+            };
+            if let Some(reply) = reply {
+                server.broadcast_message(ServerChannel::ServerMessages, bincode::serialize(&reply).unwrap());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use bevy_renet::renet::{RenetClient, RenetServer};
+    use demo_bevy::{connection_config, Difficulty};
+
+    fn send(app: &mut App, client: &mut RenetClient, client_id: ClientId, command: ClientCommand) {
+        client.send_message(ClientChannel::Command, bincode::serialize(&command).unwrap());
+        let mut server = app.world.resource_mut::<RenetServer>();
+        for packet in client.get_packets_to_send() {
+            server.process_packet_from(&packet, client_id).unwrap();
+        }
+        app.update();
+    }
+
+    #[test]
+    fn ready_only_in_waiting_room_and_majority_restarts() {
+        let mut app = App::new();
+        let mut server = RenetServer::new(connection_config());
+        let mut lobby = ServerLobby::default();
+        let mut clients = Vec::new();
+        for raw in 1..=3 {
+            let client_id = ClientId::from_raw(raw);
+            server.add_connection(client_id);
+            let entity = app.world.spawn((
+                Player { id: client_id, asset: SKINS[0].to_string(), name: String::new() },
+                PlayerState::default(),
+            )).id();
+            lobby.players.insert(client_id, entity);
+            clients.push((client_id, entity, RenetClient::new(connection_config())));
+        }
+        app.insert_resource(server)
+            .init_resource::<RoomMembers>()
+            .insert_resource(lobby)
+            .insert_resource(State::new(RunState::Waiting))
+            .init_resource::<RestartVotes>()
+            .init_resource::<Director>()
+            .add_event::<RestartRound>()
+            .add_event::<ProtocolViolation>()
+            .add_systems(Update, receive_client_commands);
+
+        let (client_id, entity, client) = &mut clients[0];
+        send(&mut app, client, *client_id, ClientCommand::Ready);
+        assert!(app.world.get::<PlayerState>(*entity).unwrap().ready);
+        send(&mut app, client, *client_id, ClientCommand::PickSkin { skin: 2 });
+        assert_eq!(app.world.get::<Player>(*entity).unwrap().asset, SKINS[2]);
+        send(&mut app, client, *client_id, ClientCommand::PickDifficulty { difficulty: Difficulty::Hard });
+        assert_eq!(app.world.resource::<Director>().difficulty, Difficulty::Hard);
+        send(&mut app, client, *client_id, ClientCommand::Unready);
+        assert!(!app.world.get::<PlayerState>(*entity).unwrap().ready);
+
+        app.insert_resource(State::new(RunState::Playing));
+        send(&mut app, client, *client_id, ClientCommand::Ready);
+        assert!(!app.world.get::<PlayerState>(*entity).unwrap().ready);
+        send(&mut app, client, *client_id, ClientCommand::PickDifficulty { difficulty: Difficulty::Easy });
+        assert_eq!(app.world.resource::<Director>().difficulty, Difficulty::Hard);
+        // voting twice does not count twice
+        send(&mut app, client, *client_id, ClientCommand::VoteRestart);
+        send(&mut app, client, *client_id, ClientCommand::VoteRestart);
+        assert!(app.world.resource::<Events<RestartRound>>().is_empty());
+        let (client_id, _, client) = &mut clients[1];
+        send(&mut app, client, *client_id, ClientCommand::VoteRestart);
+        assert!(!app.world.resource::<Events<RestartRound>>().is_empty());
+    }
+}
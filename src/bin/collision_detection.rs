@@ -1,10 +1,28 @@
-pub struct AABB {
+use bevy::prelude::{Component, Vec3};
+
+pub struct Aabb {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+// collision box of a server entity, relative to its translation
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Collider {
     pub min: (f32, f32),
     pub max: (f32, f32),
 }
 
+impl Collider {
+    pub fn aabb(&self, translation: Vec3) -> Aabb {
+        Aabb {
+            min: (translation.x + self.min.0, translation.y + self.min.1),
+            max: (translation.x + self.max.0, translation.y + self.max.1),
+        }
+    }
+}
+
 // Function to detect collision and return depth x/y overlaping of 2 rectangles
-pub fn test_aabb_overlap(a: AABB, b: AABB) -> Option<(f32, f32)>{
+pub fn test_aabb_overlap(a: Aabb, b: Aabb) -> Option<(f32, f32)>{
     let d1x = b.min.0 - a.max.0;
     let d1y = b.min.1 - a.max.1;
     let d2x = a.min.0 - b.max.0;
@@ -43,6 +61,3 @@ pub fn test_aabb_overlap(a: AABB, b: AABB) -> Option<(f32, f32)>{
     }
 
 }
-
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
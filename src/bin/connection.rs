@@ -0,0 +1,229 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use demo_bevy::protocol::{decode, HandshakeReply, BUILD_HASH, PROTOCOL_VERSION};
+use demo_bevy::{ClientChannel, ClientCommand, ServerChannel};
+
+// gameplay messages are only read after the server welcomed us
+#[derive(Resource, Debug, Default, Clone, PartialEq, Eq)]
+pub enum Handshake {
+    #[default]
+    NotSent,
+    Sent,
+    Welcomed,
+    Rejected(String),
+}
+
+#[derive(States, Debug, Default, Hash, Clone, Copy, Eq, PartialEq)]
+pub enum ConnectionState {
+    #[default]
+    Online,
+    Lost,
+}
+
+// something went wrong with the server, the game goes to the connection lost screen
+#[derive(Event, Debug)]
+pub struct ConnectionLost {
+    pub reason: String,
+}
+
+// shown on the connection lost screen
+#[derive(Resource, Debug, Default)]
+pub struct LostReason(pub String);
+
+#[derive(Component)]
+pub struct ConnectionLostScreen;
+
+// sent with the hello, the server gives a player that dropped back to whoever has its key
+#[derive(Resource, Debug)]
+pub struct PlayerKey(pub u64);
+
+impl Default for PlayerKey {
+    fn default() -> Self {
+        PlayerKey(rand::random())
+    }
+}
+
+// room we asked for, then the one the server put us in so connecting again lands there too
+#[derive(Resource, Debug, Default)]
+pub struct RoomCode(pub String);
+
+pub fn welcomed(handshake: Res<Handshake>) -> bool {
+    *handshake == Handshake::Welcomed
+}
+
+// new RenetClient and transport, with a fresh connect token unless running unsecure
+#[cfg(feature = "transport")]
+pub fn connect(
+    settings: &demo_bevy::settings::ClientSettings,
+) -> Result<(RenetClient, bevy_renet::renet::transport::NetcodeClientTransport, u64), String> {
+    use bevy_renet::renet::transport::{ClientAuthentication, NetcodeClientTransport};
+    use demo_bevy::auth::{name_to_user_data, request_connect_token};
+    use demo_bevy::connection_config;
+    use std::{net::UdpSocket, time::SystemTime};
+
+    let client = RenetClient::new(connection_config());
+    let server_addr = settings.server;
+    let socket = UdpSocket::bind(settings.bind_addr()).map_err(|e| format!("Could not open a socket: {}", e))?;
+    let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+    let (client_id, authentication) = if settings.unsecure {
+        let client_id = current_time.as_millis() as u64;
+        (client_id, ClientAuthentication::Unsecure {
+            client_id,
+            protocol_id: settings.protocol_id,
+            server_addr,
+            user_data: Some(name_to_user_data(&settings.name)),
+        })
+    } else {
+        // server gives us the client id inside the token
+        let connect_token = request_connect_token(settings.token_addr(), &settings.name)
+            .map_err(|e| format!("Could not get a connect token from {}: {}", settings.token_addr(), e))?;
+        (connect_token.client_id, ClientAuthentication::Secure { connect_token })
+    };
+
+    let transport = NetcodeClientTransport::new(current_time, authentication, socket).map_err(|e| e.to_string())?;
+    Ok((client, transport, client_id))
+}
+
+pub fn send_hello(mut client: ResMut<RenetClient>, mut handshake: ResMut<Handshake>, player_key: Res<PlayerKey>, room: Res<RoomCode>) {
+    if *handshake != Handshake::NotSent {
+        return;
+    }
+    let message = bincode::serialize(&ClientCommand::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        build_hash: BUILD_HASH,
+        player_key: player_key.0,
+        room: room.0.clone(),
+    }).unwrap();
+    client.send_message(ClientChannel::Command, message);
+    *handshake = Handshake::Sent;
+}
+
+pub fn receive_handshake_reply(
+    mut client: ResMut<RenetClient>,
+    mut handshake: ResMut<Handshake>,
+    mut lost: EventWriter<ConnectionLost>,
+) {
+    while let Some(message) = client.receive_message(ServerChannel::Handshake) {
+        match decode(&message) {
+            Ok(HandshakeReply::Welcome) => *handshake = Handshake::Welcomed,
+            Ok(HandshakeReply::Rejected { reason }) => {
+                println!("Server rejected us: {}", reason);
+                lost.send(ConnectionLost { reason: reason.clone() });
+                *handshake = Handshake::Rejected(reason);
+            }
+            Err(e) => {
+                lost.send(ConnectionLost { reason: format!("Server sent a handshake we can not read: {}", e) });
+            }
+        }
+    }
+}
+
+#[cfg(feature = "transport")]
+pub fn transport_errors(
+    mut errors: EventReader<bevy_renet::renet::transport::NetcodeTransportError>,
+    mut lost: EventWriter<ConnectionLost>,
+) {
+    for e in errors.read() {
+        lost.send(ConnectionLost { reason: e.to_string() });
+    }
+}
+
+// drops the connection and shows why, only the first reason counts
+pub fn lose_connection(
+    mut commands: Commands,
+    mut events: EventReader<ConnectionLost>,
+    handshake: Res<Handshake>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+    #[cfg(feature = "transport")] transport: Option<ResMut<bevy_renet::renet::transport::NetcodeClientTransport>>,
+) {
+    let Some(event_reason) = events.read().next().map(|event| event.reason.clone()) else {
+        return;
+    };
+    events.clear();
+    let reason = match &*handshake {
+        Handshake::Rejected(reason) => reason.clone(),
+        _ => event_reason,
+    };
+    println!("Connection lost: {}", reason);
+    #[cfg(feature = "transport")]
+    if let Some(mut transport) = transport {
+        transport.disconnect();
+        commands.remove_resource::<bevy_renet::renet::transport::NetcodeClientTransport>();
+    }
+    commands.remove_resource::<RenetClient>();
+    commands.insert_resource(LostReason(reason));
+    next_state.set(ConnectionState::Lost);
+}
+
+pub fn spawn_connection_lost_screen(mut commands: Commands, reason: Res<LostReason>) {
+    commands.spawn((TextBundle::from_sections([
+        TextSection::new(
+            "CONNECTION LOST\n",
+            TextStyle {
+                font_size: 40.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+        TextSection::new(
+            format!("{}\n\n", reason.0),
+            TextStyle {
+                font_size: 25.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+        TextSection::new(
+            "ENTER - connect again    ESC - quit",
+            TextStyle {
+                font_size: 30.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+    ]).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(300.0),
+        left: Val::Px(300.0),
+        max_width: Val::Px(700.0),
+        ..default()
+    }),ConnectionLostScreen));
+}
+
+pub fn despawn_connection_lost_screen(mut commands: Commands, screen: Query<Entity, With<ConnectionLostScreen>>) {
+    for entity in screen.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+// menu of the connection lost screen
+#[cfg(feature = "transport")]
+pub fn connection_lost_menu(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut exit: EventWriter<AppExit>,
+    mut next_state: ResMut<NextState<ConnectionState>>,
+    mut screen: Query<&mut Text, With<ConnectionLostScreen>>,
+    mut commands: Commands,
+    settings: Res<demo_bevy::settings::ClientSettings>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        exit.send(AppExit);
+    }
+    if !keyboard_input.just_pressed(KeyCode::Enter) {
+        return;
+    }
+    match connect(&settings) {
+        Ok((client, transport, client_id)) => {
+            commands.insert_resource(client);
+            commands.insert_resource(transport);
+            commands.insert_resource(crate::CurrentClientId(client_id));
+            next_state.set(ConnectionState::Online);
+        }
+        Err(reason) => {
+            for mut text in screen.iter_mut() {
+                text.sections[1].value = format!("{}\n\n", reason);
+            }
+        }
+    }
+}
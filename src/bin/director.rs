@@ -0,0 +1,154 @@
+use std::time::Duration;
+use bevy::prelude::*;
+use rand::Rng;
+use demo_bevy::{Difficulty, Player};
+use crate::line_clear::RowCleared;
+use crate::lives::Lives;
+use crate::{PlayerState, BOX_SPEED};
+
+const ROW_PRESSURE: f32 = 0.02; // every cleared row, a team that keeps up gets more
+const PLAYER_PRESSURE: f32 = 0.1; // every player in the game after the first
+const MAX_CRANES: usize = 3;
+const MAX_CRANE_SPEEDUP: f32 = 2.0; // crane speed at full pressure, in box speeds
+const SPAWN_INTERVAL: (f32, f32) = (1.0, 7.0); // seconds between loads at no pressure
+const HARDEST_SPAWN_INTERVAL: (f32, f32) = (0.5, 2.0);
+
+// where a preset starts, how fast it gets harder and how hard it gets, pressure goes from 0 to 1
+struct Preset {
+    start: f32,
+    per_second: f32,
+    max: f32,
+}
+
+fn preset(difficulty: Difficulty) -> Preset {
+    match difficulty {
+        Difficulty::Easy => Preset { start: 0.0, per_second: 1.0 / 400.0, max: 0.6 },
+        Difficulty::Normal => Preset { start: 0.1, per_second: 1.0 / 240.0, max: 1.0 },
+        Difficulty::Hard => Preset { start: 0.35, per_second: 1.0 / 120.0, max: 1.0 },
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+// decides how often and how fast the cranes bring boxes, from the time played, rows cleared and players
+#[derive(Resource, Debug, Default)]
+pub struct Director {
+    pub difficulty: Difficulty,
+    elapsed: f32,
+    rows: u32,
+    players: usize,
+}
+
+impl Director {
+    // new match, the picked difficulty stays
+    fn reset(&mut self) {
+        *self = Director { difficulty: self.difficulty, ..default() };
+    }
+
+    fn pressure(&self) -> f32 {
+        let preset = preset(self.difficulty);
+        let pressure = preset.start
+            + self.elapsed * preset.per_second
+            + self.rows as f32 * ROW_PRESSURE
+            + self.players.saturating_sub(1) as f32 * PLAYER_PRESSURE;
+        pressure.clamp(0.0, preset.max)
+    }
+
+    // time until the cranes bring the next loads
+    pub fn spawn_interval(&self, rng: &mut impl Rng) -> Duration {
+        let pressure = self.pressure();
+        let min = lerp(SPAWN_INTERVAL.0, HARDEST_SPAWN_INTERVAL.0, pressure);
+        let max = lerp(SPAWN_INTERVAL.1, HARDEST_SPAWN_INTERVAL.1, pressure);
+        Duration::from_secs_f32(rng.gen_range(min..=max))
+    }
+
+    pub fn crane_speed(&self) -> f32 {
+        BOX_SPEED * lerp(1.0, MAX_CRANE_SPEEDUP, self.pressure())
+    }
+
+    // cranes that each bring a load at the same time
+    pub fn cranes(&self) -> usize {
+        (1 + (self.pressure() * MAX_CRANES as f32) as usize).min(MAX_CRANES)
+    }
+
+    // a different column for every crane, any of them, a row only clears when all are filled
+    pub fn drop_columns(&self, rng: &mut impl Rng, columns: usize) -> Vec<usize> {
+        rand::seq::index::sample(rng, columns, self.cranes().min(columns)).into_vec()
+    }
+}
+
+// the crane moves a whole tick at once, at a low tick rate and full speed that is more than the
+// column width, so the load drops when the step passes the column and not only when it lands near it
+pub fn reached_column(before: f32, after: f32, column: f32) -> bool {
+    (before - column) * (after - column) <= 0.0
+}
+
+pub fn reset_director(mut director: ResMut<Director>) {
+    director.reset();
+}
+
+pub fn track_pressure(
+    time: Res<Time>,
+    mut director: ResMut<Director>,
+    mut cleared: EventReader<RowCleared>,
+    players: Query<(&PlayerState, &Lives), With<Player>>,
+) {
+    director.elapsed += time.delta_seconds();
+    director.rows += cleared.read().count() as u32;
+    director.players = players.iter().filter(|(player_state, lives)| !player_state.dead || lives.respawning()).count();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn pressure_grows_with_time_rows_and_players() {
+        let mut director = Director { players: 1, ..default() };
+        assert_eq!(director.cranes(), 1);
+        assert_eq!(director.crane_speed(), BOX_SPEED * lerp(1.0, MAX_CRANE_SPEEDUP, 0.1));
+        let calm = director.pressure();
+        director.rows = 5;
+        director.players = 3;
+        assert!(director.pressure() > calm);
+        director.elapsed = 1000.0;
+        assert_eq!(director.pressure(), 1.0);
+        assert_eq!(director.cranes(), MAX_CRANES);
+        assert_eq!(director.crane_speed(), BOX_SPEED * MAX_CRANE_SPEEDUP);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        assert!(director.spawn_interval(&mut rng) <= Duration::from_secs_f32(HARDEST_SPAWN_INTERVAL.1));
+
+        // easy never gets as bad and a new match starts calm again
+        director.difficulty = Difficulty::Easy;
+        assert!(director.pressure() < 1.0);
+        director.reset();
+        assert_eq!(director.difficulty, Difficulty::Easy);
+        assert_eq!(director.pressure(), 0.0);
+    }
+
+    #[test]
+    fn cranes_drop_into_every_column() {
+        let director = Director::default();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let mut picked = [false; 14];
+        for _ in 0..500 {
+            for column in director.drop_columns(&mut rng, picked.len()) {
+                picked[column] = true;
+            }
+        }
+        assert!(picked.iter().all(|picked| *picked));
+    }
+
+    #[test]
+    fn a_long_step_still_reaches_the_column() {
+        let step = BOX_SPEED * MAX_CRANE_SPEEDUP / 10.0; // 10 ticks per second
+        assert!(reached_column(94.6, 94.6 + step, 105.0)); // 10 away before and after the step
+        assert!(reached_column(105.0, 105.0 - step, 100.0));
+        assert!(reached_column(90.0, 100.0, 100.0));
+        assert!(!reached_column(40.0, 40.0 + step, 100.0 + step));
+        assert!(!reached_column(101.0, 101.0 + step, 100.0)); // already past and moving away
+    }
+}
@@ -1,29 +1,27 @@
-use bevy::app::AppExit;
 use bevy::prelude::*;
-use bevy_renet::renet::RenetServer;
-use demo_bevy::{ServerChannel, ServerMessages};
 
-#[derive(Resource)]
-pub struct GameOverTimer{
-    pub timer: Timer,
-    pub second_timer: Timer
-}
 #[derive(Component)]
 pub struct Dead;
-pub fn game_over_spawn(mut commands: &mut Commands,  asset_server: &Res<AssetServer>,dead: &Query<Entity, With<Dead>>){
+// everything the game over screen spawned, the results stay until the next match starts
+#[derive(Component)]
+pub struct GameOverScreen;
+// goes away when the server waits for ready again, the ready text takes its place
+#[derive(Component)]
+pub struct GameOverText;
+pub fn game_over_spawn(commands: &mut Commands,  asset_server: &Res<AssetServer>,dead: &Query<Entity, With<Dead>>, results: String){
     for entity in dead.iter() {
         commands.entity(entity).despawn();
     }
-    commands.spawn(SpriteBundle{
+    commands.spawn((SpriteBundle{
         transform: Transform::from_translation(Vec3::new(0.0, 200.0, 5.0)),
         texture: asset_server.load("origbig.png"),
         ..default()
-    });
-    commands.spawn(SpriteBundle{
+    }, GameOverScreen));
+    commands.spawn((SpriteBundle{
         transform: Transform::from_translation(Vec3::new(100.0, 250.0, 6.0)),
         texture: asset_server.load("game_over.png"),
         ..default()
-    });
+    }, GameOverScreen));
     commands.spawn((TextBundle::from_sections([TextSection::new(
         "all of the players died",
         TextStyle {
@@ -36,41 +34,19 @@ pub fn game_over_spawn(mut commands: &mut Commands,  asset_server: &Res<AssetSer
         bottom: Val::Px(250.0),
         left: Val::Px(380.0),
         ..default()
-    }),));
+    }),GameOverScreen, GameOverText));
     commands.spawn((TextBundle::from_sections([TextSection::new(
-        "exiting ...",
+        results,
         TextStyle {
-            font_size: 40.0,
+            font_size: 30.0,
             color: Color::rgb(0.0, 0.0, 0.0),
             ..default()
         },
     )]).with_style(Style {
         position_type: PositionType::Absolute,
-        bottom: Val::Px(210.0),
+        top: Val::Px(470.0),
         left: Val::Px(490.0),
         ..default()
-    }),));
+    }),GameOverScreen));
 
 }
-pub fn exit_game(mut exit: &mut EventWriter<AppExit>) {
-    exit.send(AppExit);
-}
-
-pub fn exit_app_timer(mut over_timer: ResMut<GameOverTimer>,
-                      mut server: ResMut<RenetServer>,
-                      time: Res<Time>,
-                      mut exit: EventWriter<AppExit>) {
-    over_timer.timer.tick(time.delta());
-    over_timer.second_timer.tick(time.delta());
-    if over_timer.timer.finished(){
-        let message = bincode::serialize(&ServerMessages::ExitWindow {}).unwrap();
-        server.broadcast_message(ServerChannel::ServerMessages, message);
-    }
-    if over_timer.second_timer.finished(){
-        exit.send(AppExit);
-    }
-
-}
-
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
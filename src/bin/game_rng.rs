@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// every random gameplay choice goes through this, so a seed and the inputs replay the same match
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng {
+    fixed_seed: Option<u64>, // from --seed, otherwise every match gets a new one
+    seed: u64,
+    #[deref]
+    rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(fixed_seed: Option<u64>) -> Self {
+        let seed = fixed_seed.unwrap_or_else(|| rand::thread_rng().gen());
+        GameRng {
+            fixed_seed,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // start the sequence again, with the fixed seed or a fresh one
+    pub fn reseed(&mut self) {
+        *self = GameRng::new(self.fixed_seed);
+    }
+}
+
+pub fn start_match_rng(mut rng: ResMut<GameRng>) {
+    rng.reseed();
+    println!("Match started with seed {}", rng.seed());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use bevy_renet::renet::RenetServer;
+    use demo_bevy::level::Level;
+    use demo_bevy::{connection_config, BoxKind, PickupKind};
+    use crate::director::Director;
+    use crate::rooms::RoomMembers;
+    use crate::{setup_timer_and_spawner, spawn_fake_box, BoxSpawner, FakeBox, FakeboxState};
+
+    // column, box or pickup and the time to the next load of every crane load
+    type Load = (usize, Option<BoxKind>, Option<PickupKind>, Duration);
+
+    fn spawned_loads(seed: u64) -> Vec<Load> {
+        let mut app = App::new();
+        app.insert_resource(RenetServer::new(connection_config()))
+            .init_resource::<RoomMembers>()
+            .init_resource::<Level>()
+            .init_resource::<Director>()
+            .init_resource::<Time>()
+            .insert_resource(GameRng::new(Some(seed)))
+            .add_systems(Startup, setup_timer_and_spawner)
+            .add_systems(Update, spawn_fake_box);
+        app.update();
+        let mut loads = Vec::new();
+        for _ in 0..20 {
+            let delay = app.world.resource::<BoxSpawner>().timer.duration();
+            app.world.resource_mut::<Time>().advance_by(delay);
+            app.update();
+            let delay = app.world.resource::<BoxSpawner>().timer.duration();
+            let mut spawned: Vec<Load> = app.world
+                .query_filtered::<(&FakeboxState, Option<&BoxKind>, Option<&PickupKind>), With<FakeBox>>()
+                .iter(&app.world)
+                .map(|(state, kind, pickup)| (state.index_padnutia, kind.copied(), pickup.copied(), delay))
+                .collect();
+            spawned.sort_by_key(|load| load.0);
+            loads.extend(spawned);
+            let fake_boxes: Vec<Entity> = app.world.query_filtered::<Entity, With<FakeBox>>().iter(&app.world).collect();
+            for entity in fake_boxes {
+                app.world.despawn(entity);
+            }
+        }
+        loads
+    }
+
+    #[test]
+    fn same_seed_spawns_the_same_loads() {
+        let loads = spawned_loads(42);
+        assert_eq!(loads.len(), 20);
+        assert_eq!(spawned_loads(42), loads);
+        assert_ne!(spawned_loads(43), loads);
+    }
+}
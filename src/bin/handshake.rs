@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy_renet::renet::{ClientId, RenetServer};
+use demo_bevy::protocol::{check_hello, decode, HandshakeReply};
+use demo_bevy::{ClientChannel, ClientCommand, ServerChannel};
+
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+const KICK_DELAY: Duration = Duration::from_secs(1); // lets the rejection reach the client before disconnecting
+
+// connected clients that did not say hello yet, and rejected ones waiting to be kicked
+#[derive(Resource, Debug, Default)]
+pub struct Handshakes {
+    pending: HashMap<ClientId, (String, Timer)>,
+    kicks: Vec<(ClientId, Timer)>,
+}
+
+impl Handshakes {
+    pub fn start(&mut self, client_id: ClientId, name: String) {
+        self.pending.insert(client_id, (name, Timer::new(HELLO_TIMEOUT, TimerMode::Once)));
+    }
+
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.pending.remove(&client_id);
+        self.kicks.retain(|(id, _)| *id != client_id);
+    }
+
+    // tells the client why and disconnects it a moment later
+    pub fn kick(&mut self, server: &mut RenetServer, client_id: ClientId, reason: String) {
+        if self.kicks.iter().any(|(id, _)| *id == client_id) {
+            return;
+        }
+        println!("Player {} kicked: {}", client_id, reason);
+        let message = bincode::serialize(&HandshakeReply::Rejected { reason }).unwrap();
+        server.send_message(client_id, ServerChannel::Handshake, message);
+        self.pending.remove(&client_id);
+        self.kicks.push((client_id, Timer::new(KICK_DELAY, TimerMode::Once)));
+    }
+}
+
+// client passed the handshake, its player can be spawned in the room it asked for
+#[derive(Event, Debug, Clone)]
+pub struct ClientWelcomed {
+    pub client_id: ClientId,
+    pub name: String,
+    pub player_key: u64,
+    pub room: String,
+}
+
+pub fn receive_hello(
+    time: Res<Time>,
+    mut server: ResMut<RenetServer>,
+    mut handshakes: ResMut<Handshakes>,
+    mut welcomed: EventWriter<ClientWelcomed>,
+) {
+    let pending: Vec<ClientId> = handshakes.pending.keys().copied().collect();
+    for client_id in pending {
+        let Some(message) = server.receive_message(client_id, ClientChannel::Command) else {
+            continue;
+        };
+        // a client from another version may not even send something we can read
+        let result = match decode::<ClientCommand>(&message) {
+            Ok(ClientCommand::Hello { protocol_version, build_hash, player_key, room }) => {
+                check_hello(protocol_version, build_hash).map(|()| (player_key, room))
+            }
+            Ok(_) => Err("The game has to say hello first. Update the game.".to_string()),
+            Err(e) => {
+                println!("Player {} sent a bad hello: {}", client_id, e);
+                Err("Could not read the hello message. Update the game.".to_string())
+            }
+        };
+        match result {
+            Ok((player_key, room)) => {
+                let (name, _) = handshakes.pending.remove(&client_id).unwrap();
+                let message = bincode::serialize(&HandshakeReply::Welcome).unwrap();
+                server.send_message(client_id, ServerChannel::Handshake, message);
+                welcomed.send(ClientWelcomed { client_id, name, player_key, room });
+            }
+            Err(reason) => handshakes.kick(&mut server, client_id, reason),
+        }
+    }
+
+    let mut timed_out = Vec::new();
+    for (client_id, (_, timer)) in handshakes.pending.iter_mut() {
+        if timer.tick(time.delta()).finished() {
+            timed_out.push(*client_id);
+        }
+    }
+    for client_id in timed_out {
+        handshakes.kick(&mut server, client_id, "No hello from the game in time. Update the game.".to_string());
+    }
+
+    handshakes.kicks.retain_mut(|(client_id, timer)| {
+        if timer.tick(time.delta()).finished() {
+            server.disconnect(*client_id);
+            return false;
+        }
+        true
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy_renet::renet::RenetClient;
+    use demo_bevy::connection_config;
+    use demo_bevy::protocol::{BUILD_HASH, PROTOCOL_VERSION};
+
+    fn say_hello(app: &mut App, client: &mut RenetClient, client_id: ClientId, build_hash: u64) -> HandshakeReply {
+        app.world.resource_mut::<Handshakes>().start(client_id, "Jakub".to_string());
+        let message = bincode::serialize(&ClientCommand::Hello { protocol_version: PROTOCOL_VERSION, build_hash, player_key: 42, room: "ABCD".to_string() }).unwrap();
+        client.send_message(ClientChannel::Command, message);
+        let mut server = app.world.resource_mut::<RenetServer>();
+        server.add_connection(client_id);
+        for packet in client.get_packets_to_send() {
+            server.process_packet_from(&packet, client_id).unwrap();
+        }
+
+        app.update();
+        let mut server = app.world.resource_mut::<RenetServer>();
+        for packet in server.get_packets_to_send(client_id).unwrap() {
+            client.process_packet(&packet);
+        }
+        bincode::deserialize(&client.receive_message(ServerChannel::Handshake).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn welcomes_same_build_and_rejects_others() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<Handshakes>()
+            .add_event::<ClientWelcomed>()
+            .insert_resource(RenetServer::new(connection_config()))
+            .add_systems(Update, receive_hello);
+
+        let mut client = RenetClient::new(connection_config());
+        assert_eq!(say_hello(&mut app, &mut client, ClientId::from_raw(1), BUILD_HASH), HandshakeReply::Welcome);
+        let welcomed: Vec<_> = app.world.resource_mut::<Events<ClientWelcomed>>().drain().collect();
+        assert_eq!(welcomed[0].name, "Jakub");
+        assert_eq!(welcomed[0].player_key, 42);
+        assert_eq!(welcomed[0].room, "ABCD");
+
+        let mut client = RenetClient::new(connection_config());
+        let reply = say_hello(&mut app, &mut client, ClientId::from_raw(2), BUILD_HASH ^ 1);
+        assert!(matches!(reply, HandshakeReply::Rejected { .. }));
+        assert_eq!(app.world.resource::<Handshakes>().kicks.len(), 1);
+    }
+}
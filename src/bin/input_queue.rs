@@ -0,0 +1,107 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use demo_bevy::protocol::decode;
+use demo_bevy::{ClientChannel, InputBundle, PlayerInput, SequencedInput};
+use crate::violations::ProtocolViolation;
+use crate::ServerLobby;
+use crate::rooms::RoomServer;
+
+const MAX_QUEUED_INPUTS: usize = 8; // older inputs are dropped so a fast client does not build up lag
+
+// inputs of one client waiting for their tick, one is simulated per tick
+#[derive(Component, Debug, Default)]
+pub struct InputQueue {
+    pub inputs: VecDeque<SequencedInput>,
+    pub last_sequence: u64, // acked back to the client
+    last_received: u64, // bundles repeat inputs, anything up to this one was seen already
+}
+
+impl InputQueue {
+    // takes the inputs of the bundle the server has not seen, late and repeated ones are dropped
+    fn receive(&mut self, bundle: &InputBundle) {
+        for sequenced in bundle.inputs.iter() {
+            if sequenced.sequence <= self.last_received {
+                continue;
+            }
+            self.last_received = sequenced.sequence;
+            self.inputs.push_back(*sequenced);
+            if self.inputs.len() > MAX_QUEUED_INPUTS {
+                self.inputs.pop_front();
+            }
+        }
+    }
+}
+
+// movement update cita input kanal, kazdy tick pride balik poslednych inputov
+pub fn receive_player_input(
+    mut server: RoomServer,
+    lobby: Res<ServerLobby>,
+    mut queues: Query<&mut InputQueue>,
+    mut violations: EventWriter<ProtocolViolation>,
+) {
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
+            let bundle: InputBundle = match decode(&message) {
+                Ok(bundle) => bundle,
+                Err(error) => {
+                    violations.send(ProtocolViolation { client_id, channel: "input", error });
+                    continue;
+                }
+            };
+            if let Some(mut queue) = lobby.players.get(&client_id).and_then(|entity| queues.get_mut(*entity).ok()) {
+                queue.receive(&bundle);
+            }
+        }
+    }
+}
+
+// each tick takes the next input of every player, keeps the last one when the queue runs dry
+pub fn apply_queued_input(mut players: Query<(&mut PlayerInput, &mut InputQueue)>) {
+    for (mut player_input, mut queue) in players.iter_mut() {
+        if let Some(next) = queue.inputs.pop_front() {
+            *player_input = next.input;
+            queue.last_sequence = next.sequence;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bundle(sequences: std::ops::RangeInclusive<u64>) -> InputBundle {
+        InputBundle {
+            inputs: sequences.map(|sequence| SequencedInput {
+                sequence,
+                input: PlayerInput { right: sequence % 2 == 0, ..default() },
+            }).collect(),
+        }
+    }
+
+    #[test]
+    fn repeated_and_late_inputs_are_dropped() {
+        let mut queue = InputQueue::default();
+        queue.receive(&bundle(1..=3));
+        queue.receive(&bundle(2..=5)); // 4 and 5 are new
+        queue.receive(&bundle(1..=4)); // came late, nothing new
+        // 6 and 7 were lost with their packets, the next bundle still carries them
+        queue.receive(&bundle(5..=8));
+        let sequences: Vec<u64> = queue.inputs.iter().map(|sequenced| sequenced.sequence).collect();
+        assert_eq!(sequences, (1..=8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn holds_last_input_when_nothing_arrives() {
+        let mut world = World::new();
+        let mut queue = InputQueue::default();
+        queue.receive(&bundle(1..=2));
+        let player = world.spawn((PlayerInput::default(), queue)).id();
+        let mut schedule = bevy::ecs::schedule::Schedule::default();
+        schedule.add_systems(apply_queued_input);
+        for _ in 0..4 {
+            schedule.run(&mut world);
+        }
+        assert!(world.get::<PlayerInput>(player).unwrap().right);
+        assert_eq!(world.get::<InputQueue>(player).unwrap().last_sequence, 2);
+    }
+}
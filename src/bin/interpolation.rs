@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use demo_bevy::movement::Animation;
+use demo_bevy::protocol::decode;
+use demo_bevy::snapshot::{SnapshotHistory, WorldSnapshot};
+use demo_bevy::{ClientChannel, ServerChannel, DEFAULT_TICK_RATE};
+use crate::NetworkMapping;
+use crate::sprite_animation::Player;
+use crate::connection::ConnectionLost;
+
+const MAX_SNAPSHOTS: usize = 32; // about half a second at 60 ticks
+const CLOCK_SNAP_TICKS: f64 = 10.0; // render clock jumps instead of sliding when this far off
+const CLOCK_CORRECTION: f64 = 0.1;
+const ENTITY_Z: f32 = 2.0;
+
+// how far in the past the client renders and how long it guesses when snapshots stop coming
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct InterpolationConfig {
+    pub tick_rate: f64,
+    pub delay: f64, // seconds
+    pub max_extrapolation: f64, // seconds
+}
+
+impl Default for InterpolationConfig {
+    fn default() -> Self {
+        InterpolationConfig {
+            tick_rate: DEFAULT_TICK_RATE,
+            delay: 0.1,
+            max_extrapolation: 0.25,
+        }
+    }
+}
+
+// server tick the client is showing right now, fractional
+#[derive(Resource, Debug, Default)]
+pub struct RenderClock {
+    latest_tick: Option<u64>,
+    since_latest: f64, // seconds since the newest snapshot arrived
+    render_tick: f64,
+}
+
+impl RenderClock {
+    fn receive(&mut self, tick: u64) {
+        if self.latest_tick.is_none_or(|latest| tick > latest) {
+            self.latest_tick = Some(tick);
+            self.since_latest = 0.0;
+        }
+    }
+
+    fn advance(&mut self, delta: f64, config: &InterpolationConfig) {
+        let Some(latest_tick) = self.latest_tick else {
+            return;
+        };
+        self.since_latest += delta;
+        let target = latest_tick as f64 + (self.since_latest - config.delay) * config.tick_rate;
+        self.render_tick += delta * config.tick_rate;
+        if (target - self.render_tick).abs() > CLOCK_SNAP_TICKS {
+            self.render_tick = target;
+        } else {
+            self.render_tick += (target - self.render_tick) * CLOCK_CORRECTION;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+    pub tick: u64,
+    pub translation: Vec3,
+    pub scale: Vec3,
+    pub animation: Animation,
+}
+
+// snapshots of one networked entity, oldest first
+#[derive(Component, Debug, Default)]
+pub struct SnapshotBuffer {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl SnapshotBuffer {
+    pub fn push(&mut self, snapshot: Snapshot) {
+        // unreliable channel, packets can come late or twice
+        if self.snapshots.back().is_some_and(|last| snapshot.tick <= last.tick) {
+            return;
+        }
+        self.snapshots.push_back(snapshot);
+        if self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+        }
+    }
+
+    // translation, scale and animation at a fractional tick, None until the first snapshot comes
+    pub fn sample(&mut self, render_tick: f64, max_extrapolation_ticks: f64) -> Option<(Vec3, Vec3, Animation)> {
+        // keep one snapshot older than render_tick to interpolate from
+        while self.snapshots.len() > 2 && (self.snapshots[1].tick as f64) <= render_tick {
+            self.snapshots.pop_front();
+        }
+        let first = *self.snapshots.front()?;
+        if render_tick <= first.tick as f64 || self.snapshots.len() == 1 {
+            return Some((first.translation, first.scale, first.animation));
+        }
+        let second = self.snapshots[1];
+        let t = (render_tick - first.tick as f64) / (second.tick - first.tick) as f64;
+        // past the newest snapshot keep moving the same way, but only for a while
+        let max_t = 1.0 + max_extrapolation_ticks / (second.tick - first.tick) as f64;
+        let t = t.min(max_t) as f32;
+        let scale = if t <= 1.0 { first.scale.lerp(second.scale, t) } else { second.scale };
+        // animation is not blended, it switches when the newer snapshot is reached
+        let animation = if t < 1.0 { first.animation } else { second.animation };
+        Some((first.translation.lerp(second.translation, t), scale, animation))
+    }
+}
+
+// world states decoded so far, newer snapshots are deltas against one of them
+#[derive(Resource, Debug, Default)]
+pub struct ReceivedSnapshots(SnapshotHistory);
+
+// reads position packets from the server into the buffers
+pub fn receive_snapshots(
+    mut client: ResMut<RenetClient>,
+    network_mapping: Res<NetworkMapping>,
+    mut clock: ResMut<RenderClock>,
+    mut received: ResMut<ReceivedSnapshots>,
+    mut buffers: Query<(&mut SnapshotBuffer, Option<&Player>)>,
+    mut lost: EventWriter<ConnectionLost>,
+) {
+    let mut newest = None;
+    while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
+        let snapshot: WorldSnapshot = match decode(&message) {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                lost.send(ConnectionLost { reason: format!("Server sent a snapshot we can not read: {}", e) });
+                return;
+            }
+        };
+        // base already dropped or never received, the next one will be against an older ack
+        let Some(state) = snapshot.decode(&received.0) else {
+            continue;
+        };
+        clock.receive(snapshot.tick);
+        // snapshot ids are Entity::to_bits() of the server entities
+        for entity in state.values() {
+            if let Some((mut buffer, player)) = network_mapping.0.get(&Entity::from_bits(entity.id))
+                .and_then(|client_entity| buffers.get_mut(*client_entity).ok()) {
+                let scale = match player {
+                    Some(_) => Vec3::new(if entity.pose.flipped() { -4.0 } else { 4.0 }, 4.0, 4.0),
+                    None => Vec3::ONE,
+                };
+                buffer.push(Snapshot {
+                    tick: snapshot.tick,
+                    translation: entity.translation(ENTITY_Z),
+                    scale,
+                    animation: entity.pose.animation(),
+                });
+            }
+        }
+        received.0.push(snapshot.tick, state);
+        newest = received.0.latest_tick();
+    }
+    if let Some(tick) = newest {
+        client.send_message(ClientChannel::SnapshotAck, bincode::serialize(&tick).unwrap());
+    }
+}
+
+pub fn interpolate_snapshots(
+    time: Res<Time>,
+    config: Res<InterpolationConfig>,
+    mut clock: ResMut<RenderClock>,
+    mut query: Query<(&mut Transform, &mut SnapshotBuffer, Option<&mut Player>)>,
+) {
+    clock.advance(time.delta_seconds_f64(), &config);
+    let max_extrapolation_ticks = config.max_extrapolation * config.tick_rate;
+    for (mut transform, mut buffer, player) in query.iter_mut() {
+        if let Some((translation, scale, animation)) = buffer.sample(clock.render_tick, max_extrapolation_ticks) {
+            transform.translation = translation;
+            transform.scale = scale;
+            if let Some(mut player) = player {
+                player.animation = animation;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(tick: u64, x: f32) -> Snapshot {
+        Snapshot { tick, translation: Vec3::new(x, 0.0, 0.0), scale: Vec3::ONE, animation: Animation::Running }
+    }
+
+    #[test]
+    fn interpolates_between_nearest_snapshots() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(10, 0.0));
+        buffer.push(snapshot(12, 20.0));
+        buffer.push(snapshot(14, 60.0));
+
+        assert_eq!(buffer.sample(11.0, 0.0).unwrap().0.x, 10.0);
+        assert_eq!(buffer.sample(13.0, 0.0).unwrap().0.x, 40.0);
+    }
+
+    #[test]
+    fn extrapolation_stops_at_limit() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(10, 0.0));
+        buffer.push(snapshot(11, 10.0));
+
+        assert_eq!(buffer.sample(12.0, 3.0).unwrap().0.x, 20.0);
+        assert_eq!(buffer.sample(30.0, 3.0).unwrap().0.x, 40.0);
+    }
+
+    #[test]
+    fn late_snapshots_are_dropped() {
+        let mut buffer = SnapshotBuffer::default();
+        buffer.push(snapshot(10, 0.0));
+        buffer.push(snapshot(12, 20.0));
+        buffer.push(snapshot(11, 500.0));
+
+        assert_eq!(buffer.sample(11.0, 0.0).unwrap().0.x, 10.0);
+    }
+}
@@ -0,0 +1,140 @@
+use bevy::prelude::*;
+use demo_bevy::{BoxKind, PickupKind, Player, RunState, ServerChannel, ServerMessages};
+use crate::pickups::{Pickup, PowerUps};
+use crate::lives::Lives;
+use crate::director::Director;
+use crate::handshake::ClientWelcomed;
+use crate::score::Scores;
+use crate::{Box, FakeBox, PlayerState, ServerLobby};
+use crate::rooms::RoomServer;
+
+// everything a client that joined in the middle of a match has missed,
+// runs after its own player was spawned or taken back, spectators have none
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn sync_joined_clients(
+    mut welcomed: EventReader<ClientWelcomed>,
+    mut server: RoomServer,
+    run_state: Res<State<RunState>>,
+    lobby: Res<ServerLobby>,
+    scores: Res<Scores>,
+    director: Res<Director>,
+    players: Query<(Entity, &Player, &Transform, &PlayerState, Option<&Lives>)>,
+    boxes: Query<(Entity, &Transform, &BoxKind), Or<(With<Box>, With<FakeBox>)>>,
+    pickups: Query<(Entity, &Transform, &PickupKind), Or<(With<Pickup>, With<FakeBox>)>>,
+    power_ups: Query<&PowerUps>,
+) {
+    for ClientWelcomed { client_id, .. } in welcomed.read() {
+        let mut messages = Vec::new();
+        for (entity, player, transform, player_state, lives) in players.iter() {
+            if let Some(lives) = lives {
+                messages.push(lives.message(player.id));
+            }
+            // the respawn creates it again for everybody
+            if lives.is_some_and(Lives::respawning) {
+                continue;
+            }
+            if player_state.dead {
+                // not drawn anymore, the score panel still shows them
+                messages.push(ServerMessages::YouDead { id: player.id });
+                continue;
+            }
+            if player.id != *client_id {
+                messages.push(ServerMessages::PlayerCreate {
+                    id: player.id,
+                    entity,
+                    translation: transform.translation.into(),
+                    asset: player.asset.clone(),
+                    name: player.name.clone(),
+                });
+            }
+            if player_state.ready && *run_state.get() == RunState::Waiting {
+                messages.push(ServerMessages::YouReady { id: player.id });
+            }
+        }
+        // boxes on the crane too, snapshots only move what the client already has
+        for (entity, transform, kind) in boxes.iter() {
+            messages.push(ServerMessages::SpawnBox { entity, translation: transform.translation.into(), kind: *kind });
+        }
+        for (entity, transform, kind) in pickups.iter() {
+            messages.push(ServerMessages::SpawnPickup { entity, translation: transform.translation.into(), kind: *kind });
+        }
+        // a player taken back keeps what it collected
+        if let Some(power_ups) = lobby.players.get(client_id).and_then(|entity| power_ups.get(*entity).ok()) {
+            messages.push(power_ups.message());
+        }
+        messages.push(scores.update());
+        messages.push(ServerMessages::Difficulty { difficulty: director.difficulty });
+        messages.push(ServerMessages::JoinSync { state: *run_state.get() });
+        if !lobby.players.contains_key(client_id) {
+            messages.push(ServerMessages::Spectating { queued: false });
+        }
+        for message in messages {
+            server.send_message(*client_id, ServerChannel::ServerMessages, bincode::serialize(&message).unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use bevy_renet::renet::{ClientId, RenetClient, RenetServer};
+    use demo_bevy::connection_config;
+
+    #[test]
+    fn late_joiner_gets_boxes_dead_players_and_state() {
+        let mut app = App::new();
+        let client_id = ClientId::from_raw(9);
+        let mut server = RenetServer::new(connection_config());
+        server.add_connection(client_id);
+        app.insert_resource(server)
+            .init_resource::<RoomMembers>()
+            .insert_resource(State::new(RunState::Playing))
+            .init_resource::<ServerLobby>()
+            .init_resource::<Scores>()
+            .init_resource::<Director>()
+            .add_event::<ClientWelcomed>()
+            .add_systems(Update, sync_joined_clients);
+        let player = |id: u64| Player { id: ClientId::from_raw(id), asset: String::new(), name: String::new() };
+        let own = app.world.spawn((player(9), Transform::default(), PlayerState::default())).id();
+        app.world.resource_mut::<ServerLobby>().players.insert(client_id, own);
+        app.world.spawn((player(1), Transform::default(), PlayerState::default()));
+        app.world.spawn((player(2), Transform::default(), PlayerState { dead: true, ..default() }));
+        let mut respawning = Lives::new(2);
+        respawning.lose();
+        app.world.spawn((player(3), Transform::default(), PlayerState { dead: true, ..default() }, respawning));
+        app.world.spawn((Transform::from_xyz(60.0, 35.0, 2.0), Box, BoxKind::Plain));
+        app.world.spawn((Transform::from_xyz(-500.0, 500.0, 2.0), FakeBox, BoxKind::Ice));
+
+        app.world.send_event(ClientWelcomed { client_id, name: String::new(), player_key: 0, room: String::new() });
+        app.update();
+
+        let mut client = RenetClient::new(connection_config());
+        for packet in app.world.resource_mut::<RenetServer>().get_packets_to_send(client_id).unwrap() {
+            client.process_packet(&packet);
+        }
+        let mut messages = Vec::new();
+        while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
+            messages.push(bincode::deserialize::<ServerMessages>(&message).unwrap());
+        }
+        let count = |f: fn(&ServerMessages) -> bool| messages.iter().filter(|message| f(message)).count();
+        assert_eq!(count(|m| matches!(m, ServerMessages::PlayerCreate { .. })), 1);
+        assert_eq!(count(|m| matches!(m, ServerMessages::YouDead { .. })), 1);
+        assert_eq!(count(|m| matches!(m, ServerMessages::Lives { lives: 1, .. })), 1);
+        assert_eq!(count(|m| matches!(m, ServerMessages::SpawnBox { .. })), 2);
+        assert!(matches!(messages.last(), Some(ServerMessages::JoinSync { state: RunState::Playing })));
+
+        // without a player of its own it is told to watch
+        app.world.resource_mut::<ServerLobby>().players.clear();
+        app.world.send_event(ClientWelcomed { client_id, name: String::new(), player_key: 0, room: String::new() });
+        app.update();
+        for packet in app.world.resource_mut::<RenetServer>().get_packets_to_send(client_id).unwrap() {
+            client.process_packet(&packet);
+        }
+        let mut last = None;
+        while let Some(message) = client.receive_message(ServerChannel::ServerMessages) {
+            last = Some(bincode::deserialize::<ServerMessages>(&message).unwrap());
+        }
+        assert!(matches!(last, Some(ServerMessages::Spectating { queued: false })));
+    }
+}
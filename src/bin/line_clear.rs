@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use bevy::prelude::*;
+use demo_bevy::level::Level;
+use demo_bevy::{ServerChannel, ServerMessages};
+use crate::{box_floor, Box, BoxState, BOX_SIZE};
+use crate::pickups::LineBomb;
+use crate::rooms::RoomServer;
+
+// height of every next row stacked on the floor row (see handle_collisions)
+const ROW_HEIGHT: f32 = BOX_SIZE - 1.0;
+// how far a box can be pushed away from a row and still fill it, for columns see Level::column_tolerance
+const ROW_TOLERANCE: f32 = 4.0;
+
+// sent for every row that got cleared, row 0 is the one on the floor
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RowCleared {
+    pub row: usize,
+    pub boxes: usize,
+}
+
+// returns (row, column) of the grid cell the box is sitting in
+pub fn box_cell(translation: Vec3, level: &Level) -> Option<(usize, usize)> {
+    let first_row_y = box_floor(level);
+    let row = ((translation.y - first_row_y) / ROW_HEIGHT).round();
+    if row < 0.0 || (first_row_y + row * ROW_HEIGHT - translation.y).abs() > ROW_TOLERANCE {
+        return None;
+    }
+    let column = level.columns
+        .iter()
+        .position(|spawn_x| (spawn_x - translation.x).abs() < level.column_tolerance())?;
+    Some((row as usize, column))
+}
+
+// finds all rows where every column of the level holds a box, returns boxes of those rows by row
+pub fn full_rows(boxes: impl Iterator<Item = (Entity, Vec3)>, level: &Level) -> BTreeMap<usize, Vec<Entity>> {
+    let mut rows: BTreeMap<usize, (Vec<Entity>, Vec<bool>)> = BTreeMap::new();
+    for (entity, translation) in boxes {
+        if let Some((row, column)) = box_cell(translation, level) {
+            let (entities, columns) = rows.entry(row).or_insert_with(|| (Vec::new(), vec![false; level.columns.len()]));
+            entities.push(entity);
+            columns[column] = true;
+        }
+    }
+    rows.into_iter()
+        .filter(|(_, (_, columns))| columns.iter().all(|filled| *filled))
+        .map(|(row, (entities, _))| (row, entities))
+        .collect()
+}
+
+// removes full rows of settled boxes and lets everything above them fall again
+pub fn clear_full_rows(
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut boxy: Query<(Entity, &Transform, &mut BoxState), With<Box>>,
+    mut cleared: EventWriter<RowCleared>,
+    level: Res<Level>,
+) {
+    let rows = full_rows(
+        boxy.iter()
+            .filter(|(_, _, box_state)| box_state.oprety_zdola)
+            .map(|(entity, transform, _)| (entity, transform.translation)),
+        &level,
+    );
+    remove_rows(&mut commands, &mut server, &mut boxy, &mut cleared, &level, rows);
+}
+
+// a collected line-bomb clears the lowest row with settled boxes in it, full or not
+pub fn bomb_lowest_row(
+    mut bombs: EventReader<LineBomb>,
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut boxy: Query<(Entity, &Transform, &mut BoxState), With<Box>>,
+    mut cleared: EventWriter<RowCleared>,
+    level: Res<Level>,
+) {
+    if bombs.read().count() == 0 {
+        return;
+    }
+    let mut rows: BTreeMap<usize, Vec<Entity>> = BTreeMap::new();
+    for (entity, transform, box_state) in boxy.iter() {
+        if let Some((row, _)) = box_cell(transform.translation, &level).filter(|_| box_state.oprety_zdola) {
+            rows.entry(row).or_default().push(entity);
+        }
+    }
+    let rows = rows.into_iter().take(1).collect();
+    remove_rows(&mut commands, &mut server, &mut boxy, &mut cleared, &level, rows);
+}
+
+fn remove_rows(
+    commands: &mut Commands,
+    server: &mut RoomServer,
+    boxy: &mut Query<(Entity, &Transform, &mut BoxState), With<Box>>,
+    cleared: &mut EventWriter<RowCleared>,
+    level: &Level,
+    rows: BTreeMap<usize, Vec<Entity>>,
+) {
+    let Some(lowest_row) = rows.keys().next().copied() else {
+        return;
+    };
+    for (row, entities) in &rows {
+        for entity in entities {
+            let message = bincode::serialize(&ServerMessages::DespawnBox {
+                entity_to_despawn: *entity
+            }).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages, message);
+            commands.entity(*entity).despawn();
+        }
+        cleared.send(RowCleared { row: *row, boxes: entities.len() });
+    }
+    // boxes above the lowest cleared row lost their support
+    let lowest_y = box_floor(level) + lowest_row as f32 * ROW_HEIGHT;
+    for (_, transform, mut box_state) in boxy.iter_mut() {
+        if transform.translation.y > lowest_y + ROW_TOLERANCE {
+            box_state.oprety_zdola = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use bevy_renet::renet::RenetServer;
+    use demo_bevy::connection_config;
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_event::<RowCleared>();
+        app.insert_resource(RenetServer::new(connection_config()));
+        app.init_resource::<RoomMembers>();
+        app.init_resource::<Level>();
+        app.add_systems(Update, clear_full_rows);
+        app
+    }
+
+    fn spawn_box(app: &mut App, column: usize, row: usize, settled: bool) -> Entity {
+        let level = app.world.resource::<Level>();
+        let translation = Vec3::new(level.columns[column], box_floor(level) + row as f32 * ROW_HEIGHT, 2.0);
+        app.world.spawn((
+            Transform::from_translation(translation),
+            Box,
+            BoxState { oprety_zdola: settled, ..default() },
+        )).id()
+    }
+
+    fn columns(app: &App) -> usize {
+        app.world.resource::<Level>().columns.len()
+    }
+
+    fn spawn_row(app: &mut App, row: usize) -> Vec<Entity> {
+        (0..columns(app)).map(|column| spawn_box(app, column, row, true)).collect()
+    }
+
+    fn cleared_rows(app: &App) -> Vec<usize> {
+        let events = app.world.resource::<Events<RowCleared>>();
+        events.get_reader().read(events).map(|event| event.row).collect()
+    }
+
+    #[test]
+    fn full_floor_row_is_cleared() {
+        let mut app = test_app();
+        let row = spawn_row(&mut app, 0);
+        app.update();
+
+        assert_eq!(cleared_rows(&app), vec![0]);
+        assert!(row.iter().all(|entity| app.world.get_entity(*entity).is_none()));
+    }
+
+    #[test]
+    fn row_with_missing_column_stays() {
+        let mut app = test_app();
+        let row: Vec<Entity> = (1..columns(&app)).map(|column| spawn_box(&mut app, column, 0, true)).collect();
+        app.update();
+
+        assert!(cleared_rows(&app).is_empty());
+        assert!(row.iter().all(|entity| app.world.get_entity(*entity).is_some()));
+    }
+
+    #[test]
+    fn falling_boxes_do_not_fill_a_row() {
+        let mut app = test_app();
+        let last = columns(&app) - 1;
+        for column in 0..last {
+            spawn_box(&mut app, column, 0, true);
+        }
+        spawn_box(&mut app, last, 0, false);
+        app.update();
+
+        assert!(cleared_rows(&app).is_empty());
+    }
+
+    #[test]
+    fn pushed_boxes_still_fill_their_column() {
+        let mut app = test_app();
+        let row = spawn_row(&mut app, 0);
+        let tolerance = app.world.resource::<Level>().column_tolerance();
+        app.world.get_mut::<Transform>(row[3]).unwrap().translation.x += tolerance - 1.0;
+        app.update();
+
+        assert_eq!(cleared_rows(&app), vec![0]);
+    }
+
+    #[test]
+    fn line_bomb_clears_the_lowest_row_even_with_gaps() {
+        let mut app = test_app();
+        app.add_event::<LineBomb>();
+        app.add_systems(Update, bomb_lowest_row);
+        let bottom: Vec<Entity> = (2..6).map(|column| spawn_box(&mut app, column, 0, true)).collect();
+        let above = spawn_box(&mut app, 3, 1, true);
+        app.update();
+        assert!(bottom.iter().all(|entity| app.world.get_entity(*entity).is_some()));
+
+        app.world.send_event(LineBomb);
+        app.update();
+        assert_eq!(cleared_rows(&app), vec![0]);
+        assert!(bottom.iter().all(|entity| app.world.get_entity(*entity).is_none()));
+        assert!(!app.world.get::<BoxState>(above).unwrap().oprety_zdola);
+    }
+
+    #[test]
+    fn only_full_rows_are_cleared_and_boxes_above_fall() {
+        let mut app = test_app();
+        let bottom = spawn_row(&mut app, 0);
+        let middle: Vec<Entity> = (0..5).map(|column| spawn_box(&mut app, column, 1, true)).collect();
+        let top = spawn_row(&mut app, 2);
+        app.update();
+
+        assert_eq!(cleared_rows(&app), vec![0, 2]);
+        assert!(bottom.iter().chain(&top).all(|entity| app.world.get_entity(*entity).is_none()));
+        for entity in middle {
+            assert!(!app.world.get::<BoxState>(entity).unwrap().oprety_zdola);
+        }
+    }
+}
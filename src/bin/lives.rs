@@ -0,0 +1,176 @@
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use demo_bevy::level::Level;
+use demo_bevy::movement::JumpState;
+use demo_bevy::{Player, ServerChannel, ServerMessages};
+use crate::{Box, BoxState, FakeboxState, PlayerState, BOX_SIZE};
+use crate::rooms::RoomServer;
+
+const RESPAWN_TIME: Duration = Duration::from_secs(3); // off the field after losing a life
+const INVULNERABLE_TIME: Duration = Duration::from_secs(2); // boxes fall through the player after it comes back
+const CRANE_GAP: f32 = BOX_SIZE * 2.0; // stacks closer to the crane than this are no place to respawn
+
+// lives every player starts a round with, from the server settings
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct StartingLives(pub u32);
+
+#[derive(Component, Debug)]
+pub struct Lives {
+    pub left: u32,
+    respawn: Option<Timer>,
+    invulnerable: Option<Timer>,
+    changed: bool, // everybody gets Lives at the end of the tick
+}
+
+impl Lives {
+    pub fn new(left: u32) -> Self {
+        Lives { left: left.max(1), respawn: None, invulnerable: None, changed: true }
+    }
+
+    // extra life pickup
+    pub fn add(&mut self) {
+        self.left += 1;
+        self.changed = true;
+    }
+
+    // a box fell on the player, true when it has a life left and comes back after RESPAWN_TIME
+    pub fn lose(&mut self) -> bool {
+        self.left = self.left.saturating_sub(1);
+        self.changed = true;
+        if self.left == 0 {
+            return false;
+        }
+        self.respawn = Some(Timer::new(RESPAWN_TIME, TimerMode::Once));
+        true
+    }
+
+    pub fn respawning(&self) -> bool {
+        self.respawn.is_some()
+    }
+
+    pub fn invulnerable(&self) -> bool {
+        self.invulnerable.is_some()
+    }
+
+    // true on the tick the player should be back on the field
+    fn tick(&mut self, delta: Duration) -> bool {
+        if self.invulnerable.as_mut().is_some_and(|timer| timer.tick(delta).finished()) {
+            self.invulnerable = None;
+        }
+        if !self.respawn.as_mut().is_some_and(|timer| timer.tick(delta).finished()) {
+            return false;
+        }
+        self.respawn = None;
+        self.invulnerable = Some(Timer::new(INVULNERABLE_TIME, TimerMode::Once));
+        true
+    }
+
+    pub fn message(&self, id: ClientId) -> ServerMessages {
+        ServerMessages::Lives { id, lives: self.left }
+    }
+}
+
+// top of the stack in every column and the x of everything that is about to fall,
+// the column furthest from the falling boxes wins, lower stacks break ties
+fn safe_spawn(level: &Level, settled: &[Vec3], falling: &[f32]) -> Vec3 {
+    let tolerance = level.column_tolerance();
+    let columns: Vec<(f32, f32, f32)> = level.columns.iter()
+        .map(|x| {
+            let top = settled.iter()
+                .filter(|translation| (translation.x - x).abs() < tolerance)
+                .map(|translation| translation.y + BOX_SIZE - 1.0)
+                .fold(level.arena.floor, f32::max);
+            let distance = falling.iter().map(|falling_x| (falling_x - x).abs()).fold(f32::INFINITY, f32::min);
+            (*x, top, distance)
+        })
+        .collect();
+    let below_crane: Vec<&(f32, f32, f32)> = columns.iter().filter(|(_, top, _)| *top + CRANE_GAP < level.crane.drop_height).collect();
+    // a full arena still needs a place, the player is invulnerable for a while anyway
+    let candidates = if below_crane.is_empty() { columns.iter().collect() } else { below_crane };
+    let (x, y, _) = candidates.into_iter()
+        .max_by(|(_, top_a, distance_a), (_, top_b, distance_b)| distance_a.total_cmp(distance_b).then(top_b.total_cmp(top_a)))
+        .unwrap();
+    Vec3::new(*x, *y, 2.0)
+}
+
+#[allow(clippy::type_complexity)]
+pub fn respawn_players(
+    mut server: RoomServer,
+    time: Res<Time>,
+    level: Res<Level>,
+    mut players: Query<(Entity, &Player, &mut Transform, &mut PlayerState, &mut JumpState, &mut Lives), Without<Box>>,
+    boxes: Query<(&Transform, &BoxState), With<Box>>,
+    crane: Query<&FakeboxState>,
+) {
+    for (entity, player, mut transform, mut player_state, mut jump_state, mut lives) in players.iter_mut() {
+        if !lives.tick(time.delta()) {
+            continue;
+        }
+        let settled: Vec<Vec3> = boxes.iter().filter(|(_, box_state)| box_state.oprety_zdola).map(|(transform, _)| transform.translation).collect();
+        let falling: Vec<f32> = boxes.iter().filter(|(_, box_state)| !box_state.oprety_zdola).map(|(transform, _)| transform.translation.x)
+            .chain(crane.iter().map(|fakebox_state| level.columns[fakebox_state.index_padnutia]))
+            .collect();
+        transform.translation = safe_spawn(&level, &settled, &falling);
+        *player_state = PlayerState::default();
+        *jump_state = JumpState::default();
+
+        // clients removed it when it lost the life
+        let message = bincode::serialize(&ServerMessages::PlayerCreate {
+            id: player.id,
+            entity,
+            translation: transform.translation.into(),
+            asset: player.asset.clone(),
+            name: player.name.clone(),
+        }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+        let message = bincode::serialize(&ServerMessages::Invulnerable { id: player.id, seconds: INVULNERABLE_TIME.as_secs_f32() }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+}
+
+pub fn send_lives(mut server: RoomServer, mut players: Query<(&Player, &mut Lives)>) {
+    for (player, mut lives) in players.iter_mut() {
+        if !lives.changed {
+            continue;
+        }
+        lives.changed = false;
+        server.broadcast_message(ServerChannel::ServerMessages, bincode::serialize(&lives.message(player.id)).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_life_is_for_good_and_respawn_is_invulnerable() {
+        let mut lives = Lives::new(2);
+        assert!(lives.lose());
+        assert!(lives.respawning());
+        assert!(!lives.tick(RESPAWN_TIME - Duration::from_millis(100)));
+        assert!(lives.tick(Duration::from_millis(200)));
+        assert!(!lives.respawning());
+        assert!(lives.invulnerable());
+        lives.tick(INVULNERABLE_TIME);
+        assert!(!lives.invulnerable());
+        assert!(!lives.lose());
+        assert_eq!(lives.left, 0);
+        assert!(!lives.respawning());
+    }
+
+    #[test]
+    fn respawn_is_away_from_falling_boxes_and_on_top_of_the_stack() {
+        let level = Level::default();
+        let (left, right) = (level.leftmost_column(), level.rightmost_column());
+        let floor = level.arena.floor;
+        // nothing falls, the lowest column wins
+        let stack = Vec3::new(left, floor - 3.0, 2.0);
+        assert_ne!(safe_spawn(&level, &[stack], &[]).x, left);
+        // box falling on the left, the right end is furthest
+        assert_eq!(safe_spawn(&level, &[], &[left]), Vec3::new(right, floor, 2.0));
+        // stack on the right end, the player stands on it
+        let on_stack = safe_spawn(&level, &[Vec3::new(right, floor - 3.0, 2.0)], &[left]);
+        assert_eq!(on_stack, Vec3::new(right, floor - 3.0 + BOX_SIZE - 1.0, 2.0));
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use bevy_renet::renet::RenetClient;
+use demo_bevy::{ClientChannel, ClientCommand, Difficulty, ReadyText, SKINS};
+use crate::Ready;
+
+const CHAT_LINES: usize = 6;
+const HELP: &str = "T - chat   R - ready   1-3 - skin   E/N/H - difficulty   V - vote restart   ESC - leave";
+
+// last chat lines and lobby news
+#[derive(Resource, Debug, Default)]
+pub struct ChatLog {
+    lines: VecDeque<String>,
+}
+
+impl ChatLog {
+    pub fn push(&mut self, line: String) {
+        self.lines.push_back(line);
+        if self.lines.len() > CHAT_LINES {
+            self.lines.pop_front();
+        }
+    }
+}
+
+// line being typed, movement keys are ignored meanwhile
+#[derive(Resource, Debug, Default)]
+pub struct ChatInput {
+    pub typing: bool,
+    text: String,
+}
+
+#[derive(Component)]
+pub struct ChatText;
+
+// server gave us no player, we only watch the match
+#[derive(Resource, Debug, Default)]
+pub struct Spectator {
+    pub watching: bool,
+    pub queued: bool, // get a player once the server waits for a round
+}
+
+#[derive(Component)]
+pub struct SpectatorText;
+
+// input is only sent for our own player
+pub fn has_player(spectator: Res<Spectator>) -> bool {
+    !spectator.watching
+}
+
+fn send_command(client: &mut RenetClient, command: &ClientCommand) {
+    client.send_message(ClientChannel::Command, bincode::serialize(command).unwrap());
+}
+
+pub fn lobby_keys(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    chat: Res<ChatInput>,
+    mut client: ResMut<RenetClient>,
+    ready: Query<(), With<Ready>>,
+    spectator: Res<Spectator>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if chat.typing {
+        return;
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyR) {
+        // Ready text is there once the server confirmed it
+        let command = if ready.is_empty() { ClientCommand::Ready } else { ClientCommand::Unready };
+        send_command(&mut client, &command);
+    }
+    for (skin, key) in [KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3].into_iter().enumerate().take(SKINS.len()) {
+        if keyboard_input.just_pressed(key) {
+            send_command(&mut client, &ClientCommand::PickSkin { skin: skin as u8 });
+        }
+    }
+    // the server only takes it in the waiting room
+    for (difficulty, key) in [(Difficulty::Easy, KeyCode::KeyE), (Difficulty::Normal, KeyCode::KeyN), (Difficulty::Hard, KeyCode::KeyH)] {
+        if keyboard_input.just_pressed(key) {
+            send_command(&mut client, &ClientCommand::PickDifficulty { difficulty });
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyJ) && spectator.watching && !spectator.queued {
+        send_command(&mut client, &ClientCommand::JoinNextRound);
+    }
+    if keyboard_input.just_pressed(KeyCode::KeyV) {
+        send_command(&mut client, &ClientCommand::VoteRestart);
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        // goes out with the packets of this frame
+        send_command(&mut client, &ClientCommand::Leave);
+        exit.send(AppExit);
+    }
+}
+
+// "/name Jakub" renames, anything else is said to everybody
+pub fn chat_input(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut chat: ResMut<ChatInput>,
+    mut client: ResMut<RenetClient>,
+) {
+    if !chat.typing {
+        if keyboard_input.just_pressed(KeyCode::KeyT) {
+            chat.typing = true;
+            characters.clear(); // the t itself
+        }
+        return;
+    }
+    for event in characters.read() {
+        chat.text.extend(event.char.chars().filter(|c| !c.is_control()));
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        chat.text.pop();
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        chat.typing = false;
+        chat.text.clear();
+    }
+    if keyboard_input.just_pressed(KeyCode::Enter) {
+        let text = std::mem::take(&mut chat.text);
+        chat.typing = false;
+        let command = match text.strip_prefix("/name ") {
+            Some(name) => ClientCommand::SetName { name: name.to_string() },
+            None => ClientCommand::Chat { text },
+        };
+        send_command(&mut client, &command);
+    }
+}
+
+pub fn spawn_chat(mut commands: Commands) {
+    commands.spawn((TextBundle::from_sections([
+        TextSection::new(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: Color::rgb(0.0, 0.0, 0.0),
+                ..default()
+            },
+        ),
+        TextSection::new(
+            HELP,
+            TextStyle {
+                font_size: 20.0,
+                color: Color::rgb(0.2, 0.2, 0.2),
+                ..default()
+            },
+        ),
+    ]).with_style(Style {
+        position_type: PositionType::Absolute,
+        bottom: Val::Px(10.0),
+        left: Val::Px(20.0),
+        ..default()
+    }),ChatText));
+}
+
+pub fn update_chat_text(log: Res<ChatLog>, chat: Res<ChatInput>, mut query: Query<&mut Text, With<ChatText>>) {
+    for mut text in query.iter_mut() {
+        text.sections[0].value = log.lines.iter().map(|line| format!("{}\n", line)).collect();
+        text.sections[1].value = if chat.typing {
+            format!("> {}_", chat.text)
+        } else {
+            HELP.to_string()
+        };
+    }
+}
+
+pub fn update_spectator_text(mut commands: Commands, spectator: Res<Spectator>, texts: Query<Entity, With<SpectatorText>>) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn();
+    }
+    if !spectator.watching {
+        return;
+    }
+    let hint = if spectator.queued { "you play the next round" } else { "PRESS -J- TO PLAY THE NEXT ROUND" };
+    commands.spawn((TextBundle::from_sections([TextSection::new(
+        format!("SPECTATING\n{}", hint),
+        TextStyle {
+            font_size: 40.0,
+            color: Color::rgb(0.0, 0.0, 0.0),
+            ..default()
+        },
+    )]).with_style(Style {
+        position_type: PositionType::Absolute,
+        top: Val::Px(120.0),
+        right: Val::Px(40.0),
+        ..default()
+    }),SpectatorText));
+}
+
+// ready text comes and goes with the rounds, spectators can not get ready
+pub fn hide_ready_text(spectator: Res<Spectator>, mut texts: Query<&mut Visibility, With<ReadyText>>) {
+    let visibility = if spectator.watching { Visibility::Hidden } else { Visibility::Inherited };
+    for mut text in texts.iter_mut() {
+        if *text != visibility {
+            *text = visibility;
+        }
+    }
+}
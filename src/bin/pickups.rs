@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use bevy::prelude::*;
+use rand::Rng;
+use demo_bevy::level::Level;
+use demo_bevy::movement::Boosts;
+use demo_bevy::{PickupKind, Player, ServerChannel, ServerMessages};
+use crate::collision_detection::{self, Collider};
+use crate::{box_floor, Box, PlayerState, BOX_SIZE, BOX_SPEED};
+use crate::lives::Lives;
+use crate::reconnect;
+use crate::rooms::RoomServer;
+
+const PICKUP_CHANCE: f64 = 0.15; // of every load the crane brings
+const PICKUPS: [PickupKind; 5] = [
+    PickupKind::ExtraLife,
+    PickupKind::DoubleJump,
+    PickupKind::SpeedBoost,
+    PickupKind::Shield,
+    PickupKind::LineBomb,
+];
+pub const PICKUP_COLLIDER: Collider = Collider { min: (12.0, 0.0), max: (60.0, 48.0) };
+const PICKUP_LIFETIME: Duration = Duration::from_secs(15); // lying on the field before it disappears
+const DOUBLE_JUMP_TIME: Duration = Duration::from_secs(15);
+const SPEED_BOOST_TIME: Duration = Duration::from_secs(10);
+const SHIELD_TIME: Duration = Duration::from_secs(20);
+
+// somebody collected a line-bomb, see line_clear::bomb_lowest_row
+#[derive(Event, Debug)]
+pub struct LineBomb;
+
+// pickup on the field, the PickupKind is a component next to it
+#[derive(Component, Debug)]
+pub struct Pickup {
+    lifetime: Timer,
+}
+
+impl Default for Pickup {
+    fn default() -> Self {
+        Pickup { lifetime: Timer::new(PICKUP_LIFETIME, TimerMode::Once) }
+    }
+}
+
+// None means the crane brings a box
+pub fn roll_pickup(rng: &mut impl Rng) -> Option<PickupKind> {
+    rng.gen_bool(PICKUP_CHANCE).then(|| PICKUPS[rng.gen_range(0..PICKUPS.len())])
+}
+
+// collected effects of one player, extra lives go to lives::Lives
+#[derive(Component, Debug, Default)]
+pub struct PowerUps {
+    timed: Vec<(PickupKind, Timer)>,
+    changed: bool, // owner gets PowerUps at the end of the tick
+}
+
+impl PowerUps {
+    fn add(&mut self, kind: PickupKind) {
+        let duration = match kind {
+            PickupKind::ExtraLife | PickupKind::LineBomb => return,
+            PickupKind::DoubleJump => DOUBLE_JUMP_TIME,
+            PickupKind::SpeedBoost => SPEED_BOOST_TIME,
+            PickupKind::Shield => SHIELD_TIME,
+        };
+        // collecting the same one again starts its time over
+        self.timed.retain(|(timed, _)| *timed != kind);
+        self.timed.push((kind, Timer::new(duration, TimerMode::Once)));
+        self.changed = true;
+    }
+
+    // new round, collected ones do not carry over
+    pub fn clear(&mut self) {
+        *self = PowerUps { changed: true, ..default() };
+    }
+
+    fn has(&self, kind: PickupKind) -> bool {
+        self.timed.iter().any(|(timed, _)| *timed == kind)
+    }
+
+    pub fn boosts(&self) -> Boosts {
+        Boosts {
+            speed: self.has(PickupKind::SpeedBoost),
+            double_jump: self.has(PickupKind::DoubleJump),
+        }
+    }
+
+    // a box fell on the player, true when the shield took it
+    pub fn take_hit(&mut self) -> bool {
+        if !self.has(PickupKind::Shield) {
+            return false;
+        }
+        self.timed.retain(|(timed, _)| *timed != PickupKind::Shield);
+        self.changed = true;
+        true
+    }
+
+    fn tick(&mut self, delta: Duration) {
+        let before = self.timed.len();
+        self.timed.retain_mut(|(_, timer)| !timer.tick(delta).finished());
+        self.changed |= self.timed.len() != before;
+    }
+
+    pub fn message(&self) -> ServerMessages {
+        ServerMessages::PowerUps {
+            timed: self.timed.iter().map(|(kind, timer)| (*kind, timer.remaining_secs())).collect(),
+        }
+    }
+}
+
+fn remove_pickup(commands: &mut Commands, server: &mut RoomServer, entity: Entity) {
+    let message = bincode::serialize(&ServerMessages::DespawnBox { entity_to_despawn: entity }).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages, message);
+    commands.entity(entity).despawn();
+}
+
+pub fn tick_power_ups(time: Res<Time>, mut players: Query<&mut PowerUps>) {
+    for mut power_ups in players.iter_mut() {
+        power_ups.tick(time.delta());
+    }
+}
+
+// pickups fall like boxes and lie on the floor or the top of a box, a box falling on them crushes them
+#[allow(clippy::type_complexity)]
+pub fn fall_pickups(
+    mut commands: Commands,
+    mut server: RoomServer,
+    time: Res<Time>,
+    level: Res<Level>,
+    mut pickups: Query<(Entity, &mut Transform, &Collider, &mut Pickup), Without<Box>>,
+    boxes: Query<(&Transform, &Collider), With<Box>>,
+) {
+    for (entity, mut transform, collider, mut pickup) in pickups.iter_mut() {
+        let crushed = boxes.iter().any(|(box_transform, box_collider)| {
+            box_transform.translation.y > transform.translation.y
+                && collision_detection::test_aabb_overlap(collider.aabb(transform.translation), box_collider.aabb(box_transform.translation)).is_some()
+        });
+        let pickup_aabb = collider.aabb(transform.translation);
+        let support = boxes.iter()
+            .filter(|(box_transform, box_collider)| {
+                let box_aabb = box_collider.aabb(box_transform.translation);
+                box_aabb.min.0 < pickup_aabb.max.0 && pickup_aabb.min.0 < box_aabb.max.0
+                    && box_transform.translation.y + BOX_SIZE - 1.0 <= transform.translation.y + 0.5
+            })
+            .map(|(box_transform, _)| box_transform.translation.y + BOX_SIZE - 1.0)
+            .fold(box_floor(&level), f32::max);
+        let fallen = transform.translation.y - BOX_SPEED * time.delta_seconds();
+        if fallen > support {
+            transform.translation.y = fallen;
+            continue;
+        }
+        transform.translation.y = support;
+        if crushed || pickup.lifetime.tick(time.delta()).finished() {
+            remove_pickup(&mut commands, &mut server, entity);
+        }
+    }
+}
+
+pub fn collect_pickups(
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut players: Query<(&Transform, &Collider, &PlayerState, &mut PowerUps, &mut Lives), With<Player>>,
+    pickups: Query<(Entity, &Transform, &Collider, &PickupKind), With<Pickup>>,
+    mut line_bombs: EventWriter<LineBomb>,
+) {
+    let mut taken = HashSet::new();
+    for (transform, collider, player_state, mut power_ups, mut lives) in players.iter_mut() {
+        if player_state.dead {
+            continue;
+        }
+        for (entity, pickup_transform, pickup_collider, kind) in pickups.iter() {
+            if taken.contains(&entity)
+                || collision_detection::test_aabb_overlap(collider.aabb(transform.translation), pickup_collider.aabb(pickup_transform.translation)).is_none() {
+                continue;
+            }
+            taken.insert(entity);
+            remove_pickup(&mut commands, &mut server, entity);
+            match kind {
+                PickupKind::LineBomb => {
+                    line_bombs.send(LineBomb);
+                }
+                PickupKind::ExtraLife => lives.add(),
+                _ => power_ups.add(*kind),
+            }
+        }
+    }
+}
+
+// away players have no client to send to, a change waits until they are back
+pub fn send_power_ups(mut server: RoomServer, mut players: Query<(&Player, &mut PowerUps), Without<reconnect::Away>>) {
+    for (player, mut power_ups) in players.iter_mut() {
+        if !power_ups.changed {
+            continue;
+        }
+        power_ups.changed = false;
+        server.send_message(player.id, ServerChannel::ServerMessages, bincode::serialize(&power_ups.message()).unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use crate::PLAYER_COLLIDER;
+    use bevy_renet::renet::{ClientId, RenetServer};
+    use demo_bevy::connection_config;
+
+    #[test]
+    fn shield_takes_a_hit_and_boosts_run_out() {
+        let mut power_ups = PowerUps::default();
+        assert!(!power_ups.take_hit());
+        power_ups.add(PickupKind::Shield);
+        power_ups.add(PickupKind::SpeedBoost);
+        assert!(power_ups.take_hit());
+        assert!(!power_ups.has(PickupKind::Shield));
+        assert!(!power_ups.take_hit());
+
+        assert!(power_ups.boosts().speed);
+        power_ups.tick(SPEED_BOOST_TIME);
+        assert_eq!(power_ups.boosts(), Boosts::default());
+    }
+
+    #[test]
+    fn walking_into_a_pickup_collects_it() {
+        let mut app = App::new();
+        app.insert_resource(RenetServer::new(connection_config()))
+            .init_resource::<RoomMembers>()
+            .add_event::<LineBomb>()
+            .add_systems(Update, collect_pickups);
+        let player = app.world.spawn((
+            Player { id: ClientId::from_raw(1), asset: String::new(), name: String::new() },
+            Transform::from_xyz(0.0, 35.0, 2.0), PLAYER_COLLIDER, PlayerState::default(), PowerUps::default(), Lives::new(1),
+        )).id();
+        let near = app.world.spawn((Transform::from_xyz(40.0, 32.0, 2.0), PICKUP_COLLIDER, Pickup::default(), PickupKind::DoubleJump)).id();
+        let bomb = app.world.spawn((Transform::from_xyz(-30.0, 32.0, 2.0), PICKUP_COLLIDER, Pickup::default(), PickupKind::LineBomb)).id();
+        let life = app.world.spawn((Transform::from_xyz(20.0, 32.0, 2.0), PICKUP_COLLIDER, Pickup::default(), PickupKind::ExtraLife)).id();
+        let far = app.world.spawn((Transform::from_xyz(300.0, 32.0, 2.0), PICKUP_COLLIDER, Pickup::default(), PickupKind::Shield)).id();
+        app.update();
+
+        assert!(app.world.get_entity(near).is_none());
+        assert!(app.world.get_entity(bomb).is_none());
+        assert!(app.world.get_entity(life).is_none());
+        assert!(app.world.get_entity(far).is_some());
+        assert!(app.world.get::<PowerUps>(player).unwrap().boosts().double_jump);
+        assert_eq!(app.world.get::<Lives>(player).unwrap().left, 2);
+        assert_eq!(app.world.resource::<Events<LineBomb>>().len(), 1);
+    }
+}
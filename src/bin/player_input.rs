@@ -1,23 +1,28 @@
 use bevy::prelude::*;
 use bevy_renet::renet::RenetClient;
 use demo_bevy::{ClientChannel, PlayerInput};
+use crate::lobby::ChatInput;
+use crate::prediction::Prediction;
 
 pub fn player_input(
-    mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
+    chat: Res<ChatInput>,
     mut player_input: ResMut<PlayerInput>,
 ) {
+    if chat.typing {
+        *player_input = PlayerInput::default();
+        return;
+    }
     player_input.left = keyboard_input.pressed(KeyCode::KeyA) || keyboard_input.pressed(KeyCode::ArrowLeft);
     player_input.right = keyboard_input.pressed(KeyCode::KeyD) || keyboard_input.pressed(KeyCode::ArrowRight);
     player_input.up = keyboard_input.pressed(KeyCode::KeyW) || keyboard_input.pressed(KeyCode::ArrowUp);
     player_input.down = keyboard_input.pressed(KeyCode::KeyS) || keyboard_input.pressed(KeyCode::ArrowDown);
-    player_input.ready = keyboard_input.pressed(KeyCode::KeyR);
 }
 
-pub fn client_send_input(player_input: Res<PlayerInput>, mut client: ResMut<RenetClient>) {
-    let input_message = bincode::serialize(&*player_input).unwrap();
+// one numbered input every tick, sent with the few before it in case a packet gets lost
+pub fn client_send_input(player_input: Res<PlayerInput>, mut prediction: ResMut<Prediction>, mut client: ResMut<RenetClient>) {
+    prediction.record(*player_input);
+    let input_message = bincode::serialize(&prediction.bundle()).unwrap();
 
     client.send_message(ClientChannel::Input, input_message);
 }
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
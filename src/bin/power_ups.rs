@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+use demo_bevy::PickupKind;
+
+const ICON_SIZE: f32 = 48.0;
+
+// power-ups of our player from ServerMessages::PowerUps, the seconds count down here between messages,
+// lives come with ServerMessages::Lives
+#[derive(Resource, Debug, Default)]
+pub struct PowerUps {
+    pub lives: u32,
+    pub timed: Vec<(PickupKind, f32)>,
+}
+
+// row of icons in the top right corner
+#[derive(Component)]
+pub struct PowerUpHud;
+
+// seconds left under the icon, index into PowerUps::timed
+#[derive(Component)]
+pub struct PowerUpTime(usize);
+
+pub fn spawn_power_up_hud(mut commands: Commands) {
+    commands.spawn((NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(20.0),
+            right: Val::Px(20.0),
+            column_gap: Val::Px(10.0),
+            ..default()
+        },
+        ..default()
+    }, PowerUpHud));
+}
+
+fn icon(parent: &mut ChildBuilder, asset_server: &AssetServer, kind: PickupKind, label: String, time: Option<PowerUpTime>) {
+    parent.spawn(NodeBundle {
+        style: Style { flex_direction: FlexDirection::Column, align_items: AlignItems::Center, ..default() },
+        ..default()
+    }).with_children(|parent| {
+        parent.spawn(ImageBundle {
+            style: Style { width: Val::Px(ICON_SIZE), height: Val::Px(ICON_SIZE), ..default() },
+            image: UiImage::new(asset_server.load(kind.sprite())),
+            ..default()
+        });
+        let mut text = parent.spawn(TextBundle::from_section(label, TextStyle {
+            font_size: 20.0,
+            color: Color::rgb(0.0, 0.0, 0.0),
+            ..default()
+        }));
+        if let Some(time) = time {
+            text.insert(time);
+        }
+    });
+}
+
+// runs when the server sends new power-ups
+pub fn update_power_up_hud(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    power_ups: Res<PowerUps>,
+    hud: Query<Entity, With<PowerUpHud>>,
+) {
+    for entity in hud.iter() {
+        commands.entity(entity).despawn_descendants().with_children(|parent| {
+            if power_ups.lives > 0 {
+                icon(parent, &asset_server, PickupKind::ExtraLife, format!("x{}", power_ups.lives), None);
+            }
+            for (index, (kind, seconds)) in power_ups.timed.iter().enumerate() {
+                icon(parent, &asset_server, *kind, format!("{:.0}", seconds.ceil()), Some(PowerUpTime(index)));
+            }
+        });
+    }
+}
+
+pub fn count_down_power_ups(
+    time: Res<Time>,
+    mut power_ups: ResMut<PowerUps>,
+    mut texts: Query<(&mut Text, &PowerUpTime)>,
+) {
+    // the server tells us when one runs out, this only keeps the numbers moving
+    let power_ups = power_ups.bypass_change_detection();
+    for (_, seconds) in power_ups.timed.iter_mut() {
+        *seconds = (*seconds - time.delta_seconds()).max(0.0);
+    }
+    for (mut text, time) in texts.iter_mut() {
+        if let Some((_, seconds)) = power_ups.timed.get(time.0) {
+            text.sections[0].value = format!("{:.0}", seconds.ceil());
+        }
+    }
+}
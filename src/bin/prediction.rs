@@ -0,0 +1,177 @@
+use std::{collections::VecDeque, time::Duration};
+use bevy::prelude::*;
+use bevy_renet::renet::RenetClient;
+use demo_bevy::level::{Arena, Level};
+use demo_bevy::movement::{move_player, Animation, Boosts, Contacts, JumpState};
+use demo_bevy::protocol::decode;
+use demo_bevy::{InputAck, InputBundle, PlayerInput, SequencedInput, ServerChannel, INPUT_REDUNDANCY};
+use crate::ControlledPlayer;
+use crate::sprite_animation::Player;
+use crate::connection::ConnectionLost;
+
+const MAX_PENDING_INPUTS: usize = 256; // a few seconds of inputs the server has not acked yet
+
+// local player moves right away with its own inputs, server answers fix it up later
+#[derive(Resource, Debug, Default)]
+pub struct Prediction {
+    next_sequence: u64,
+    pending: VecDeque<SequencedInput>, // oldest first
+    jump: JumpState,
+    contacts: Contacts,
+    boosts: Boosts, // power-ups of our player at the last ack
+    box_top: Option<f32>, // y where the server last saw us standing on a box
+    last_ack_tick: u64,
+    pub running: bool, // server moves players only while the match is on
+}
+
+impl Prediction {
+    // numbers the input of this tick and keeps it for replays
+    pub fn record(&mut self, input: PlayerInput) {
+        self.next_sequence += 1;
+        self.pending.push_back(SequencedInput { sequence: self.next_sequence, input });
+        if self.pending.len() > MAX_PENDING_INPUTS {
+            self.pending.pop_front();
+        }
+    }
+
+    // newest inputs the server has not acked, sent together so one lost packet loses nothing
+    pub fn bundle(&self) -> InputBundle {
+        let skip = self.pending.len().saturating_sub(INPUT_REDUNDANCY);
+        InputBundle { inputs: self.pending.iter().skip(skip).copied().collect() }
+    }
+
+    fn step(&mut self, transform: &mut Transform, input: &PlayerInput, arena: &Arena, delta: Duration) {
+        move_player(transform, input, &mut self.jump, self.contacts, self.boosts, arena, delta);
+        // boxes are not simulated here, only the floor and the box we stood on when the server answered
+        self.contacts.below = if transform.translation.y <= arena.floor {
+            transform.translation.y = arena.floor;
+            true
+        } else {
+            self.box_top.is_some_and(|top| (transform.translation.y - top).abs() < 0.5)
+        };
+    }
+
+    // starts from the server state and runs the inputs it has not seen yet again
+    pub fn reconcile(&mut self, ack: &InputAck, transform: &mut Transform, arena: &Arena, delta: Duration) {
+        if ack.tick <= self.last_ack_tick {
+            return; // unreliable channel, older answer came late
+        }
+        self.last_ack_tick = ack.tick;
+        while self.pending.front().is_some_and(|pending| pending.sequence <= ack.sequence) {
+            self.pending.pop_front();
+        }
+
+        transform.translation = ack.translation.into();
+        transform.scale.x = ack.scale;
+        self.jump = ack.jump.into();
+        self.contacts = ack.contacts;
+        self.boosts = ack.boosts;
+        self.box_top = (ack.contacts.below && ack.translation[1] > arena.floor).then_some(ack.translation[1]);
+        if !self.running {
+            return;
+        }
+        let pending = std::mem::take(&mut self.pending);
+        for sequenced in pending.iter() {
+            self.step(transform, &sequenced.input, arena, delta);
+        }
+        self.pending = pending;
+    }
+}
+
+// runs on the fixed tick right after the input of this tick was recorded
+pub fn predict_local_player(
+    time: Res<Time>,
+    mut prediction: ResMut<Prediction>,
+    mut query: Query<(&mut Transform, &mut Player), With<ControlledPlayer>>,
+    level: Res<Level>,
+) {
+    let Some(input) = prediction.pending.back().map(|sequenced| sequenced.input) else {
+        return;
+    };
+    for (mut transform, mut player) in query.iter_mut() {
+        if prediction.running {
+            prediction.step(&mut transform, &input, &level.arena, time.delta());
+        }
+        // own animation follows the keys right away like the movement, a dead player is removed anyway
+        player.animation = Animation::of(&input, &prediction.jump, prediction.contacts, false);
+    }
+}
+
+pub fn receive_input_ack(
+    mut client: ResMut<RenetClient>,
+    fixed_time: Res<Time<Fixed>>,
+    mut prediction: ResMut<Prediction>,
+    mut query: Query<&mut Transform, With<ControlledPlayer>>,
+    mut lost: EventWriter<ConnectionLost>,
+    level: Res<Level>,
+) {
+    while let Some(message) = client.receive_message(ServerChannel::InputAck) {
+        let ack: InputAck = match decode(&message) {
+            Ok(ack) => ack,
+            Err(e) => {
+                lost.send(ConnectionLost { reason: format!("Server sent an input ack we can not read: {}", e) });
+                return;
+            }
+        };
+        for mut transform in query.iter_mut() {
+            prediction.reconcile(&ack, &mut transform, &level.arena, fixed_time.timestep());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: Duration = Duration::from_nanos(16_666_667);
+
+    fn ack(tick: u64, sequence: u64, x: f32) -> InputAck {
+        InputAck {
+            tick,
+            sequence,
+            translation: [x, Level::default().arena.floor, 2.0],
+            scale: 4.0,
+            contacts: Contacts { below: true, ..default() },
+            ..default()
+        }
+    }
+
+    #[test]
+    fn replays_inputs_newer_than_the_ack() {
+        let mut prediction = Prediction { running: true, ..default() };
+        let arena = Level::default().arena;
+        let mut transform = Transform::from_xyz(0.0, arena.floor, 2.0);
+        let right = PlayerInput { right: true, ..default() };
+        for _ in 0..5 {
+            prediction.record(right);
+            prediction.step(&mut transform, &right, &arena, TICK);
+        }
+        let predicted = transform.translation.x;
+
+        // server simulated two of them but a box stopped us 10 units short
+        let server_x = predicted * 2.0 / 5.0 - 10.0;
+        prediction.reconcile(&ack(10, 2, server_x), &mut transform, &arena, TICK);
+        assert!((transform.translation.x - (predicted - 10.0)).abs() < 0.01);
+        assert_eq!(prediction.pending.len(), 3);
+        assert_eq!(prediction.bundle().inputs.len(), 3);
+        for _ in 0..3 {
+            prediction.record(right);
+        }
+        let sequences: Vec<u64> = prediction.bundle().inputs.iter().map(|sequenced| sequenced.sequence).collect();
+        assert_eq!(sequences, vec![5, 6, 7, 8]);
+
+        // late older answer changes nothing
+        prediction.reconcile(&ack(9, 1, 0.0), &mut transform, &arena, TICK);
+        assert!((transform.translation.x - (predicted - 10.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn waiting_room_follows_the_server() {
+        let mut prediction = Prediction::default();
+        let arena = Level::default().arena;
+        let mut transform = Transform::from_xyz(0.0, arena.floor, 2.0);
+        prediction.record(PlayerInput { left: true, ..default() });
+        prediction.reconcile(&ack(3, 0, 100.0), &mut transform, &arena, TICK);
+        assert_eq!(transform.translation.x, 100.0);
+    }
+}
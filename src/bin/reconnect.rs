@@ -0,0 +1,86 @@
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy_renet::renet::DisconnectReason;
+use demo_bevy::{Player, ServerChannel, ServerMessages};
+use crate::score::Scores;
+use crate::rooms::RoomServer;
+
+// key the client sent in its hello, the same client connecting again brings the same one
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlayerKey(pub u64);
+
+// client of this player dropped, it stays in the game until the timer runs out
+#[derive(Component, Debug)]
+pub struct Away {
+    timer: Timer,
+}
+
+// how long a dropped player waits for its client
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ReconnectGrace(pub Duration);
+
+impl ReconnectGrace {
+    // kicked players and the ones that left on purpose are not coming back
+    pub fn away(&self, reason: &DisconnectReason) -> Option<Away> {
+        if self.0.is_zero() || matches!(reason, DisconnectReason::DisconnectedByServer) {
+            return None;
+        }
+        Some(Away { timer: Timer::new(self.0, TimerMode::Once) })
+    }
+}
+
+pub fn expire_away_players(
+    time: Res<Time>,
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut scores: ResMut<Scores>,
+    mut away: Query<(Entity, &Player, &mut Away)>,
+) {
+    for (entity, player, mut away) in away.iter_mut() {
+        if !away.timer.tick(time.delta()).finished() {
+            continue;
+        }
+        println!("Player {} did not come back, removing it.", player.id);
+        commands.entity(entity).despawn();
+        scores.remove_player(player.id);
+        let message = bincode::serialize(&ServerMessages::PlayerRemove { id: player.id }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use bevy_renet::renet::{ClientId, RenetServer};
+    use demo_bevy::connection_config;
+
+    #[test]
+    fn away_player_is_removed_after_grace() {
+        let grace = ReconnectGrace(Duration::from_secs(30));
+        assert!(grace.away(&DisconnectReason::DisconnectedByServer).is_none());
+        assert!(ReconnectGrace(Duration::ZERO).away(&DisconnectReason::Transport).is_none());
+
+        let mut app = App::new();
+        let id = ClientId::from_raw(5);
+        let mut scores = Scores::default();
+        scores.add_player(id);
+        app.init_resource::<Time>()
+            .insert_resource(scores)
+            .insert_resource(RenetServer::new(connection_config()))
+            .init_resource::<RoomMembers>()
+            .add_systems(Update, expire_away_players);
+        let player = app.world.spawn((
+            Player { id, asset: String::new(), name: String::new() },
+            grace.away(&DisconnectReason::Transport).unwrap(),
+        )).id();
+
+        app.world.resource_mut::<Time>().advance_by(Duration::from_secs(29));
+        app.update();
+        assert!(app.world.get_entity(player).is_some());
+        app.world.resource_mut::<Time>().advance_by(Duration::from_secs(2));
+        app.update();
+        assert!(app.world.get_entity(player).is_none());
+        assert!(app.world.resource::<Scores>().players.is_empty());
+    }
+}
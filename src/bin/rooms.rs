@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_renet::renet::{Bytes, ClientId, DisconnectReason, RenetServer, ServerEvent};
+use rand::Rng;
+use demo_bevy::level::Level;
+use demo_bevy::protocol::{clean_text, MAX_ROOM_CODE_LEN};
+use demo_bevy::{Player, ServerChannel, ServerMessages};
+use crate::handshake::ClientWelcomed;
+use crate::violations::ProtocolViolation;
+
+const CODE_LETTERS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ"; // no I and O, they look like 1 and 0
+const CODE_LEN: usize = 4;
+
+// same for every room, from the server settings
+#[derive(Debug, Clone)]
+pub struct RoomConfig {
+    pub tick_rate: f64,
+    pub seed: Option<u64>,
+    pub net_stats: bool,
+    pub reconnect_grace: f32,
+    pub max_players: usize,
+    pub level: Level,
+    pub lives: u32,
+}
+
+// clients routed to this room, players and spectators
+#[derive(Resource, Debug, Default)]
+pub struct RoomMembers(pub HashSet<ClientId>);
+
+// RenetServer of the process seen from inside a room, broadcasts only reach its members
+#[derive(SystemParam)]
+pub struct RoomServer<'w> {
+    server: ResMut<'w, RenetServer>,
+    members: Res<'w, RoomMembers>,
+}
+
+impl RoomServer<'_> {
+    pub fn broadcast_message<I: Into<u8>, B: Into<Bytes>>(&mut self, channel_id: I, message: B) {
+        let channel_id = channel_id.into();
+        let message = message.into();
+        for client_id in self.members.0.iter() {
+            self.server.send_message(*client_id, channel_id, message.clone());
+        }
+    }
+
+    pub fn send_message<I: Into<u8>, B: Into<Bytes>>(&mut self, client_id: ClientId, channel_id: I, message: B) {
+        self.server.send_message(client_id, channel_id, message);
+    }
+
+    pub fn receive_message<I: Into<u8>>(&mut self, client_id: ClientId, channel_id: I) -> Option<Bytes> {
+        self.server.receive_message(client_id, channel_id)
+    }
+
+    pub fn disconnect(&mut self, client_id: ClientId) {
+        self.server.disconnect(client_id);
+    }
+
+    pub fn clients_id(&self) -> Vec<ClientId> {
+        self.members.0.iter().copied().filter(|client_id| self.server.is_connected(*client_id)).collect()
+    }
+}
+
+// one match, with its own lobby, run state, boxes and players
+struct Room {
+    app: App,
+}
+
+impl Room {
+    fn new(config: &RoomConfig) -> Self {
+        let mut app = crate::room_app(config);
+        app.finish();
+        app.cleanup();
+        Room { app }
+    }
+
+    fn members(&mut self) -> Mut<'_, RoomMembers> {
+        self.app.world.resource_mut::<RoomMembers>()
+    }
+
+    // players of dropped clients keep the room open until they expire
+    fn is_empty(&mut self) -> bool {
+        self.app.world.resource::<RoomMembers>().0.is_empty()
+            && self.app.world.query_filtered::<(), With<Player>>().iter(&self.app.world).next().is_none()
+    }
+}
+
+// every room runs its own app, they are not Sync so this lives on the main thread
+pub struct Rooms {
+    config: RoomConfig,
+    rooms: HashMap<String, Room>,
+}
+
+impl Rooms {
+    pub fn new(config: RoomConfig) -> Self {
+        Rooms { config, rooms: HashMap::new() }
+    }
+
+    fn free_code(&self) -> String {
+        let mut rng = rand::thread_rng();
+        loop {
+            let code: String = (0..CODE_LEN).map(|_| CODE_LETTERS[rng.gen_range(0..CODE_LETTERS.len())] as char).collect();
+            if !self.rooms.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    // joins the room with this code, creates it when nobody uses the code, no code makes a new room
+    fn join(&mut self, code: &str, client_id: ClientId) -> (String, &mut Room) {
+        let code = clean_text(code, MAX_ROOM_CODE_LEN).to_uppercase();
+        let code = if code.is_empty() { self.free_code() } else { code };
+        let config = &self.config;
+        let room = self.rooms.entry(code.clone()).or_insert_with(|| {
+            println!("Room {} opened.", code);
+            Room::new(config)
+        });
+        room.members().0.insert(client_id);
+        (code, room)
+    }
+
+    fn room_of(&mut self, client_id: ClientId) -> Option<&mut Room> {
+        self.rooms.values_mut().find(|room| room.app.world.resource::<RoomMembers>().0.contains(&client_id))
+    }
+}
+
+// welcomed clients go to the room they asked for
+pub fn route_welcomed(
+    mut welcomed: EventReader<ClientWelcomed>,
+    mut rooms: NonSendMut<Rooms>,
+    mut server: ResMut<RenetServer>,
+) {
+    for event in welcomed.read() {
+        let (code, room) = rooms.join(&event.room, event.client_id);
+        println!("Player {} ({}) joins room {}.", event.client_id, event.name, code);
+        room.app.world.send_event(ClientWelcomed { room: code.clone(), ..event.clone() });
+        let message = bincode::serialize(&ServerMessages::Room { code, level: rooms.config.level.id.clone() }).unwrap();
+        server.send_message(event.client_id, ServerChannel::ServerMessages, message);
+    }
+}
+
+// the room decides if the player waits for its client to come back
+pub fn route_disconnect(rooms: &mut Rooms, client_id: ClientId, reason: DisconnectReason) {
+    if let Some(room) = rooms.room_of(client_id) {
+        room.members().0.remove(&client_id);
+        room.app.world.send_event(ServerEvent::ClientDisconnected { client_id, reason });
+    }
+}
+
+// runs every room for one frame with the server of the process lent to it
+pub fn update_rooms(world: &mut World) {
+    let mut rooms = world.remove_non_send_resource::<Rooms>().unwrap();
+    let mut server = world.remove_resource::<RenetServer>().unwrap();
+    for room in rooms.rooms.values_mut() {
+        room.app.world.insert_resource(server);
+        room.app.update();
+        server = room.app.world.remove_resource::<RenetServer>().unwrap();
+        // kicks are handled by the handshake of the process
+        let violations: Vec<ProtocolViolation> = room.app.world.resource_mut::<Events<ProtocolViolation>>().drain().collect();
+        world.send_event_batch(violations);
+    }
+    rooms.rooms.retain(|code, room| {
+        let empty = room.is_empty();
+        if empty {
+            println!("Room {} closed.", code);
+        }
+        !empty
+    });
+    world.insert_resource(server);
+    world.insert_non_send_resource(rooms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use demo_bevy::connection_config;
+
+    #[test]
+    fn same_code_same_room_and_empty_rooms_close() {
+        let mut rooms = Rooms::new(RoomConfig { tick_rate: 60.0, seed: Some(1), net_stats: false, reconnect_grace: 0.0, max_players: 3, level: Level::default(), lives: 3 });
+        let (code, _) = rooms.join("", ClientId::from_raw(1));
+        assert_eq!(code.len(), CODE_LEN);
+        assert_eq!(rooms.join(&code.to_lowercase(), ClientId::from_raw(2)).0, code);
+        assert_eq!(rooms.join("other", ClientId::from_raw(3)).0, "OTHER");
+        assert_eq!(rooms.rooms.len(), 2);
+
+        let mut app = App::new();
+        app.insert_resource(RenetServer::new(connection_config()))
+            .add_event::<ProtocolViolation>()
+            .insert_non_send_resource(rooms)
+            .add_systems(Update, update_rooms);
+        route_disconnect(&mut app.world.non_send_resource_mut::<Rooms>(), ClientId::from_raw(3), DisconnectReason::Transport);
+        app.update();
+        let rooms = app.world.non_send_resource::<Rooms>();
+        assert_eq!(rooms.rooms.len(), 1);
+        assert!(rooms.rooms.contains_key(&code));
+    }
+
+    #[test]
+    fn broadcasts_stay_in_the_room() {
+        let mut app = App::new();
+        let mut server = RenetServer::new(connection_config());
+        let (inside, outside) = (ClientId::from_raw(1), ClientId::from_raw(2));
+        server.add_connection(inside);
+        server.add_connection(outside);
+        app.insert_resource(server)
+            .insert_resource(RoomMembers([inside].into()))
+            .add_systems(Update, |mut server: RoomServer| {
+                server.broadcast_message(ServerChannel::ServerMessages, vec![1, 2, 3]);
+            });
+        app.update();
+        let mut server = app.world.resource_mut::<RenetServer>();
+        assert!(!server.get_packets_to_send(inside).unwrap().is_empty());
+        assert!(server.get_packets_to_send(outside).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+use std::time::Duration;
+use bevy::prelude::*;
+use demo_bevy::movement::JumpState;
+use demo_bevy::level::Level;
+use demo_bevy::{Player, PlayerInput, ServerChannel, ServerMessages};
+use crate::client_commands::RestartVotes;
+use crate::score::Scores;
+use crate::{spawn_transform, Box, FakeBox, InputQueue, PlayerState, RunState};
+use crate::pickups::{Pickup, PowerUps};
+use crate::lives::{Lives, StartingLives};
+use crate::rooms::RoomServer;
+
+const RESULTS_TIME: Duration = Duration::from_secs(10); // clients look at the results before the next round
+
+// enough players voted or the results were shown long enough, start over from the waiting room
+#[derive(Event, Debug)]
+pub struct RestartRound;
+
+#[derive(Resource, Debug)]
+pub struct ResultsTimer(Timer);
+
+impl Default for ResultsTimer {
+    fn default() -> Self {
+        ResultsTimer(Timer::new(RESULTS_TIME, TimerMode::Once))
+    }
+}
+
+// everybody died, final scores go out before they are reset for the next round
+pub fn send_results(mut server: RoomServer, scores: Res<Scores>, mut results: ResMut<ResultsTimer>) {
+    results.0.reset();
+    server.broadcast_message(ServerChannel::ServerMessages, bincode::serialize(&scores.round_over()).unwrap());
+}
+
+pub fn results_timer(time: Res<Time>, mut results: ResMut<ResultsTimer>, mut restart: EventWriter<RestartRound>) {
+    if results.0.tick(time.delta()).just_finished() {
+        restart.send(RestartRound);
+    }
+}
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+pub fn restart_round(
+    mut events: EventReader<RestartRound>,
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut players: Query<(Entity, &Player, &mut Transform, &mut PlayerState, &mut JumpState, &mut PlayerInput, &mut InputQueue, &mut PowerUps, &mut Lives)>,
+    boxes: Query<Entity, Or<(With<Box>, With<FakeBox>, With<Pickup>)>>,
+    mut scores: ResMut<Scores>,
+    mut votes: ResMut<RestartVotes>,
+    mut next_state: ResMut<NextState<RunState>>,
+    level: Res<Level>,
+    starting_lives: Res<StartingLives>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+    println!("Restarting the round.");
+    for entity in boxes.iter() {
+        let message = bincode::serialize(&ServerMessages::DespawnBox { entity_to_despawn: entity }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+        commands.entity(entity).despawn();
+    }
+    for (index, (entity, player, mut transform, mut player_state, mut jump_state, mut input, mut queue, mut power_ups, mut lives)) in players.iter_mut().enumerate() {
+        *transform = spawn_transform(&level, index);
+        *player_state = PlayerState::default();
+        *jump_state = JumpState::default();
+        *input = PlayerInput::default();
+        queue.inputs.clear();
+        power_ups.clear();
+        *lives = Lives::new(starting_lives.0);
+        // dead players were removed on the clients, the ones still there skip it
+        let message = bincode::serialize(&ServerMessages::PlayerCreate {
+            id: player.id,
+            entity,
+            translation: transform.translation.into(),
+            asset: player.asset.clone(),
+            name: player.name.clone(),
+        }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+    scores.reset();
+    votes.clear();
+    next_state.set(RunState::Waiting);
+    let message = bincode::serialize(&ServerMessages::RoundRestart {}).unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages, message);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_end_with_a_new_round() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<ResultsTimer>()
+            .add_event::<RestartRound>()
+            .add_systems(Update, results_timer);
+
+        app.world.resource_mut::<Time>().advance_by(RESULTS_TIME - Duration::from_secs(1));
+        app.update();
+        assert!(app.world.resource::<Events<RestartRound>>().is_empty());
+        app.world.resource_mut::<Time>().advance_by(Duration::from_secs(2));
+        app.update();
+        assert_eq!(app.world.resource_mut::<Events<RestartRound>>().drain().count(), 1);
+        // once per game over
+        app.world.resource_mut::<Time>().advance_by(RESULTS_TIME);
+        app.update();
+        assert!(app.world.resource::<Events<RestartRound>>().is_empty());
+    }
+}
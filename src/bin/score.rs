@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use demo_bevy::{Player, ServerChannel, ServerMessages};
+use crate::line_clear::RowCleared;
+use crate::PlayerState;
+use crate::rooms::RoomServer;
+
+// POINTS
+const ROW_POINTS: u32 = 100; // every living player and the team get this for a cleared row
+const PUSH_POINTS: u32 = 5; // for pushing a box by one box width
+const SURVIVAL_POINTS: u32 = 1; // for every second alive
+const PUSH_DISTANCE: f32 = 70.0;
+
+// sent by handle_collisions every time a player moves a box
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BoxPushed {
+    pub id: ClientId,
+    pub distance: f32,
+}
+
+#[derive(Resource)]
+pub struct Scores {
+    pub team: u32,
+    pub players: HashMap<ClientId, u32>,
+    pushed: HashMap<ClientId, f32>, // push distance not yet turned into points
+    survival_timer: Timer,
+    changed: bool, // send ScoreUpdate at the end of the frame
+}
+
+impl Default for Scores {
+    fn default() -> Self {
+        Scores {
+            team: 0,
+            players: HashMap::new(),
+            pushed: HashMap::new(),
+            survival_timer: Timer::from_seconds(1.0, TimerMode::Repeating),
+            changed: false,
+        }
+    }
+}
+
+impl Scores {
+    pub fn add_player(&mut self, id: ClientId) {
+        self.players.entry(id).or_insert(0);
+        self.changed = true;
+    }
+
+    pub fn remove_player(&mut self, id: ClientId) {
+        self.players.remove(&id);
+        self.pushed.remove(&id);
+        self.changed = true;
+    }
+
+    pub fn update(&self) -> ServerMessages {
+        ServerMessages::ScoreUpdate {
+            team: self.team,
+            players: self.players.iter().map(|(id, points)| (*id, *points)).collect(),
+        }
+    }
+
+    pub fn round_over(&self) -> ServerMessages {
+        ServerMessages::RoundOver {
+            team: self.team,
+            players: self.players.iter().map(|(id, points)| (*id, *points)).collect(),
+        }
+    }
+
+    // client connected again under a new id
+    pub fn rename_player(&mut self, old: ClientId, new: ClientId) {
+        if let Some(points) = self.players.remove(&old) {
+            self.players.insert(new, points);
+        }
+        if let Some(distance) = self.pushed.remove(&old) {
+            self.pushed.insert(new, distance);
+        }
+        self.changed = true;
+    }
+
+    // new round, everybody starts from zero
+    pub fn reset(&mut self) {
+        self.team = 0;
+        self.players.values_mut().for_each(|points| *points = 0);
+        self.pushed.clear();
+        self.survival_timer.reset();
+        self.changed = true;
+    }
+
+    // every player in ids gets the points, the team gets them once
+    fn award(&mut self, ids: impl IntoIterator<Item = ClientId>, points: u32) {
+        for id in ids {
+            *self.players.entry(id).or_insert(0) += points;
+        }
+        self.team += points;
+        self.changed = true;
+    }
+}
+
+pub fn award_cleared_rows(
+    mut scores: ResMut<Scores>,
+    mut cleared: EventReader<RowCleared>,
+    players: Query<(&Player, &PlayerState)>,
+) {
+    for _ in cleared.read() {
+        // the row is a team effort so everybody still alive gets the points
+        let living = players.iter().filter(|(_, player_state)| !player_state.dead).map(|(player, _)| player.id);
+        scores.award(living, ROW_POINTS);
+    }
+}
+
+pub fn award_pushes(mut scores: ResMut<Scores>, mut pushed: EventReader<BoxPushed>) {
+    for push in pushed.read() {
+        let distance = scores.pushed.entry(push.id).or_insert(0.0);
+        *distance += push.distance;
+        let widths = (*distance / PUSH_DISTANCE) as u32;
+        if widths > 0 {
+            *distance -= widths as f32 * PUSH_DISTANCE;
+            scores.award([push.id], widths * PUSH_POINTS);
+        }
+    }
+}
+
+pub fn award_survival(mut scores: ResMut<Scores>, time: Res<Time>, players: Query<(&Player, &PlayerState)>) {
+    scores.survival_timer.tick(time.delta());
+    for _ in 0..scores.survival_timer.times_finished_this_tick() {
+        for (player, _) in players.iter().filter(|(_, player_state)| !player_state.dead) {
+            scores.award([player.id], SURVIVAL_POINTS);
+        }
+    }
+}
+
+pub fn broadcast_scores(mut scores: ResMut<Scores>, mut server: RoomServer) {
+    if !scores.changed {
+        return;
+    }
+    scores.changed = false;
+    server.broadcast_message(ServerChannel::ServerMessages, bincode::serialize(&scores.update()).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_app() -> App {
+        let mut app = App::new();
+        app.add_event::<RowCleared>();
+        app.init_resource::<Scores>();
+        app.add_systems(Update, award_cleared_rows);
+        app
+    }
+
+    fn spawn_player(app: &mut App, id: u64, dead: bool) -> ClientId {
+        let id = ClientId::from_raw(id);
+        app.world.resource_mut::<Scores>().add_player(id);
+        app.world.spawn((Player { id, asset: String::new(), name: String::new() }, PlayerState { dead, ..default() }));
+        id
+    }
+
+    fn points(app: &App, id: ClientId) -> u32 {
+        app.world.resource::<Scores>().players[&id]
+    }
+
+    #[test]
+    fn living_players_get_row_points_and_team_gets_them_once() {
+        let mut app = test_app();
+        let first = spawn_player(&mut app, 1, false);
+        let second = spawn_player(&mut app, 2, false);
+        let dead = spawn_player(&mut app, 3, true);
+        app.world.send_event(RowCleared { row: 0, boxes: 14 });
+        app.update();
+
+        assert_eq!(points(&app, first), ROW_POINTS);
+        assert_eq!(points(&app, second), ROW_POINTS);
+        assert_eq!(points(&app, dead), 0);
+        assert_eq!(app.world.resource::<Scores>().team, ROW_POINTS);
+    }
+
+    #[test]
+    fn every_cleared_row_counts() {
+        let mut app = test_app();
+        let player = spawn_player(&mut app, 1, false);
+        app.world.send_event(RowCleared { row: 0, boxes: 14 });
+        app.world.send_event(RowCleared { row: 1, boxes: 14 });
+        app.update();
+
+        assert_eq!(points(&app, player), 2 * ROW_POINTS);
+        assert_eq!(app.world.resource::<Scores>().team, 2 * ROW_POINTS);
+        assert!(app.world.resource::<Scores>().changed);
+    }
+}
@@ -1,60 +1,71 @@
-use std::{collections::HashMap};
+use std::collections::HashMap;
 use std::time::Duration;
 use bevy::{
+    app::ScheduleRunnerPlugin,
     prelude::*, };
 use bevy_renet::{
     renet::{ClientId, RenetServer, ServerEvent},
     RenetServerPlugin, };
-use rand::{thread_rng, Rng};
+use rand::Rng;
 use demo_bevy::{
-    ClientChannel, NetworkedEntities, NetworkedBoxes, Player, PlayerInput, ServerChannel,
-                ServerMessages};
-use demo_bevy::{setup_level};
-// Mutable global variable to keep track of players connected
-use std::sync::Mutex;
+    BoxKind, InputAck, PickupKind, Player, PlayerInput, ServerChannel,
+                RunState, ServerMessages, SKINS};
+use demo_bevy::movement::{move_player, Contacts, JumpState};
+use demo_bevy::level::Level;
 
-mod game_over;
 mod collision_detection;
+use collision_detection::Collider;
+mod line_clear;
+mod score;
+mod game_rng;
+mod snapshot_sync;
+mod handshake;
+mod violations;
+mod client_commands;
+mod round;
+mod input_queue;
+mod reconnect;
+mod join_sync;
+mod spectators;
+mod box_kinds;
+mod pickups;
+use pickups::PowerUps;
+mod lives;
+use lives::Lives;
+mod director;
+mod rooms;
+use rooms::RoomServer;
+use input_queue::InputQueue;
+#[cfg(feature = "transport")]
+mod token_issuer;
 
-#[macro_use]
-extern crate lazy_static;
-
-lazy_static! {
-    static ref PLAYER_COUNT: Mutex<i32> = Mutex::new(0); // players connected
-    static ref BOX: Mutex<i32> = Mutex::new(0); // boxes spawned for testing
-}
-fn increment_player_count() {
-    let mut var = PLAYER_COUNT.lock().unwrap();
-    *var += 1;
-}
-fn increment_box() {
-    let mut var = BOX.lock().unwrap();
-    *var += 1;
-}
 
 
 // GAME CONSTS
 const BOX_SPEED: f32 = 100.0;
 const BOX_SIZE: f32 = 72.0;
 const PUSH_SPEED: f32 = 2.0;
-const FLOOR_HEIGHT: f32 = 35.0;
-const PLAYER_SPEED: f32 = 400.0;
-const LEFT_WALL: f32 = -400.0;
-const RIGHT_WALL:  f32 = 600.0;
-const JUMP_VELOCITY: f32 = 500.0;
-const NUM_OF_BOXES: usize =14;
-const MOST_LEFT_BOX: f32 = -360.0;
-const MOST_RIGHT_BOX: f32 = 550.0;
-const BOX_SPAWNS: [f32; NUM_OF_BOXES] = [-360.0,-290.0,-220.0,-150.0,-80.0,-10.0,60.0,130.0,200.0,270.0,340.0,410.0,480.0,550.0];
-
-#[derive(States,Debug, Default, Hash, Clone, Eq, PartialEq, Copy)]
-enum RunState {
-    #[default]
-    Waiting,
-    Playing,
-    GameOver,
+const BOX_COLLIDER: Collider = Collider { min: (1.0, 0.0), max: (BOX_SIZE - 1.0, BOX_SIZE) };
+const PLAYER_COLLIDER: Collider = Collider { min: (5.0, 0.0), max: (75.0, 70.0) };
+
+
+
+// order of the authoritative simulation inside one FixedUpdate tick
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum GameplaySet {
+    Input,
+    Movement,
+    BoxPhysics,
+    Collisions,
+    LineClear,
+    DeathCheck,
+    Score,
+    Sync,
 }
 
+// number of the current simulation tick, sent with every snapshot
+#[derive(Resource, Default, Debug, Clone, Copy)]
+struct SimulationTick(u64);
 
 #[derive(Resource)] // will spawn boxes, resource means it can be acessed like ResMut<BoxSpawner>
 struct BoxSpawner{
@@ -72,58 +83,49 @@ struct FakeBox;  // box when it's still on the crane
 #[derive(Component)]
 struct Box;  // box in the game
 
-#[derive(Component)]
+#[derive(Component, Default)]
 struct BoxState{
     oprety_zdola: bool, // kedy gravitacia netaha dole
     _oprety_sprava: bool,
     _oprety_zlava: bool,
     oprety_zhora: bool,
-    bot_zmena: bool // zapamatat ci doslo k bot kolizii pri check ak nie tak reset oprety zdola
-}
-// player animation
-#[derive(Component)]
-struct AnimationIndices { // TO SAVE ANIMATION SLICES COUNT
-    first: usize,
-    last: usize,
-}
-#[derive(Component, Deref, DerefMut)]
-struct AnimationTimer(Timer);
-
-// player movement
-#[derive(Component)]
-struct JumpState {
-    is_jumping: bool,
-    jump_timer: Timer,
-    fall_timer: Timer,
-    can_jump: bool,
-    floor_reset: bool // nech moze skocit iba ked sa dotkne zeme od posledneho skoku
+    bot_zmena: bool, // zapamatat ci doslo k bot kolizii pri check ak nie tak reset oprety zdola
+    slide: f32, // ice box moving after a push, -1 left, 1 right, 0 stopped
+    landed_on: bool, // a box or a player landed on it this tick, fragile boxes break
 }
 #[derive(Component)]
 struct PlayerState{
     oprety_zprava: bool,
     oprety_zdola: bool,
     oprety_zlava: bool,
-    oprety_zhora: bool,
+    _oprety_zhora: bool,
     ready: bool,
     dead: bool,
 }
 
 
-
 #[derive(Debug, Default, Resource)]
 pub struct ServerLobby {
     pub players: HashMap<ClientId, Entity>,
 }
 
+// where a player starts a round, index is the order of joining
+fn spawn_transform(level: &Level, index: usize) -> Transform {
+    Transform::from_translation(level.player_spawn(index)) * Transform::from_scale(Vec3::splat(4.0))
+}
 
-impl Default for JumpState {
-    fn default() -> Self {
-        JumpState {
-            is_jumping: false,
-            floor_reset: true,
-            can_jump: true,
-            jump_timer: Timer::from_seconds(0.2, TimerMode::Once),
-            fall_timer: Timer::from_seconds(0.4, TimerMode::Once),
+// y of a box lying on the floor
+fn box_floor(level: &Level) -> f32 {
+    level.arena.floor - 3.0
+}
+
+
+impl PlayerState {
+    fn contacts(&self) -> Contacts {
+        Contacts {
+            left: self.oprety_zlava,
+            right: self.oprety_zprava,
+            below: self.oprety_zdola,
         }
     }
 }
@@ -133,116 +135,186 @@ impl Default for PlayerState {
             oprety_zprava: false,
             oprety_zdola: true,
             oprety_zlava: false,
-            oprety_zhora: false,
+            _oprety_zhora: false,
             ready: false,
             dead: false
         }
     }
 }
 
-impl Default for BoxState {
-    fn default() -> Self {
-        BoxState {
-            oprety_zdola: false,
-            _oprety_sprava: false,
-            _oprety_zlava: false,
-            oprety_zhora: false,
-            bot_zmena: false
-        }
-    }
-}
 
 // Server config
 #[cfg(feature = "transport")]
-fn add_netcode_network(app: &mut App) {
+fn add_netcode_network(app: &mut App, settings: demo_bevy::settings::ServerSettings) {
     use bevy_renet::renet::transport::{NetcodeServerTransport, ServerAuthentication, ServerConfig};
     use bevy_renet::transport::NetcodeServerPlugin;
-    use demo_bevy::{connection_config, PROTOCOL_ID};
+    use demo_bevy::connection_config;
     use std::{net::UdpSocket, time::SystemTime};
 
     app.add_plugins(NetcodeServerPlugin);
 
     let server = RenetServer::new(connection_config());
 
-    let public_addr = "127.0.0.1:5000".parse().unwrap();
-    let socket = UdpSocket::bind(public_addr).unwrap();
+    let socket = UdpSocket::bind(settings.bind_addr()).unwrap();
     let current_time: Duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
     let server_config = ServerConfig {
         current_time,
-        max_clients: 64,
-        protocol_id: PROTOCOL_ID,
-        public_addresses: vec![public_addr],
-        authentication: ServerAuthentication::Unsecure,
+        max_clients: settings.max_clients,
+        protocol_id: settings.protocol_id,
+        public_addresses: settings.public_addrs(),
+        authentication: if settings.unsecure {
+            println!("Running without connect tokens, do not use outside of development");
+            ServerAuthentication::Unsecure
+        } else {
+            token_issuer::spawn_token_issuer(&settings);
+            ServerAuthentication::Secure {
+                private_key: settings.private_key(),
+            }
+        },
     };
+    println!("Server listening on {} ({:?})", settings.bind_addr(), server_config.public_addresses);
 
     let transport = NetcodeServerTransport::new(server_config, socket).unwrap();
     app.insert_resource(server);
     app.insert_resource(transport);
+    app.insert_resource(settings);
 }
 
 // Main app setup game
 fn main() {
+    #[cfg(feature = "transport")]
+    let settings = demo_bevy::settings::ServerSettings::from_args();
+    #[cfg(feature = "transport")]
+    let config = rooms::RoomConfig {
+        tick_rate: settings.tick_rate,
+        seed: settings.seed,
+        net_stats: settings.net_stats,
+        reconnect_grace: settings.reconnect_grace,
+        max_players: settings.max_players,
+        level: Level::load(&settings.level).unwrap_or_else(|e| {
+            eprintln!("can not load the level: {}", e);
+            std::process::exit(2);
+        }),
+        lives: settings.lives,
+    };
+    #[cfg(not(feature = "transport"))]
+    let config = rooms::RoomConfig { tick_rate: demo_bevy::DEFAULT_TICK_RATE, seed: None, net_stats: false, reconnect_grace: 0.0, max_players: SKINS.len(), level: Level::default(), lives: 3 };
+
     let mut app = App::new();
-    app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest())
-        .set(WindowPlugin {
-            primary_window: Some(Window {
-                title: "Game".to_string(),
-                resolution: bevy::window::WindowResolution::new(1200., 700.),
-                resizable: false,
-                visible: false,
-                ..Default::default()
-            }),
-            ..Default::default()
-        }));
+    // no window, renderer or assets, server only simulates and sends state
+    app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / config.tick_rate))));
     app.add_plugins(RenetServerPlugin);
-    app.insert_resource(ServerLobby::default());
-    app.init_state::<RunState>();
     #[cfg(feature = "transport")]
-    add_netcode_network(&mut app);
-    app.add_systems(Update, (check_if_all_ready).run_if(in_state(RunState::Waiting)));
-    app.add_systems(Update, (server_update_system, server_network_sync));
-    app.add_systems(
-        Update,
-        (
-            craning,
-            spawn_fake_box,
-            fall_boxes,
-            handle_collisions,
-            check_all_dead,
-            //despawn_boxes,
-        ).run_if(in_state(RunState::Playing)),
-    );
-
-    app.add_systems(FixedUpdate, move_players_system.run_if(in_state(RunState::Playing)));
-    app.add_systems(Update, game_over::exit_app_timer.run_if(in_state(RunState::GameOver)));
-    app.add_systems(Startup, (setup_level, setup_timer_and_spawner));
+    add_netcode_network(&mut app, settings);
+    app.init_resource::<handshake::Handshakes>();
+    app.add_event::<handshake::ClientWelcomed>();
+    app.add_event::<violations::ProtocolViolation>();
+    app.init_resource::<violations::Violations>();
+    app.insert_non_send_resource(rooms::Rooms::new(config));
+    app.add_systems(Update, (
+        server_update_system,
+        handshake::receive_hello,
+        rooms::route_welcomed,
+        rooms::update_rooms,
+    ).chain());
+    // violations from the rooms are forwarded by update_rooms
+    app.add_systems(PostUpdate, violations::punish_violations);
 
     app.run();
+}
 
-
+// one match, every room of the server runs its own copy of it
+fn room_app(config: &rooms::RoomConfig) -> App {
+    let mut app = App::new();
+    // updated by rooms::update_rooms, not by a runner
+    app.add_plugins(MinimalPlugins.build().disable::<ScheduleRunnerPlugin>());
+    app.add_plugins(TransformPlugin);
+    app.insert_resource(Time::<Fixed>::from_hz(config.tick_rate));
+    app.insert_resource(config.level.clone());
+    app.init_resource::<SimulationTick>();
+    app.insert_resource(game_rng::GameRng::new(config.seed));
+    app.init_resource::<rooms::RoomMembers>();
+    app.add_event::<ServerEvent>();
+    app.add_event::<handshake::ClientWelcomed>();
+    app.add_event::<violations::ProtocolViolation>();
+    app.insert_resource(ServerLobby::default());
+    app.init_state::<RunState>();
+    app.add_event::<line_clear::RowCleared>();
+    app.add_event::<score::BoxPushed>();
+    app.add_event::<pickups::LineBomb>();
+    app.init_resource::<score::Scores>();
+    app.init_resource::<snapshot_sync::SnapshotBaselines>();
+    app.insert_resource(snapshot_sync::SnapshotStats::new(config.net_stats));
+    app.add_systems(Update, (check_if_all_ready).run_if(in_state(RunState::Waiting)).after(client_commands::receive_client_commands));
+    app.init_resource::<client_commands::RestartVotes>();
+    app.insert_resource(reconnect::ReconnectGrace(Duration::from_secs_f32(config.reconnect_grace.max(0.0))));
+    app.add_systems(Update, reconnect::expire_away_players.after(join_players));
+    app.add_event::<round::RestartRound>();
+    app.init_resource::<spectators::Spectators>();
+    app.insert_resource(spectators::MaxPlayers(config.max_players));
+    app.insert_resource(lives::StartingLives(config.lives));
+    app.init_resource::<director::Director>();
+    app.add_systems(Update, (
+        join_players,
+        join_sync::sync_joined_clients,
+        client_commands::receive_client_commands,
+        spectators::receive_spectator_commands,
+        round::restart_round,
+    ).chain());
+    app.add_systems(Update, spectators::promote_spectators.run_if(in_state(RunState::Waiting)).after(round::restart_round));
+    app.add_systems(PostUpdate, score::broadcast_scores);
+    app.add_systems(Update, snapshot_sync::report_snapshot_stats);
+
+    // all gameplay runs on the fixed tick so it does not depend on frame rate
+    app.configure_sets(
+        FixedUpdate,
+        (
+            GameplaySet::Input,
+            GameplaySet::Movement,
+            GameplaySet::BoxPhysics,
+            GameplaySet::Collisions,
+            GameplaySet::LineClear,
+            GameplaySet::DeathCheck,
+            GameplaySet::Score,
+            GameplaySet::Sync,
+        ).chain(),
+    );
+    for set in [GameplaySet::Movement, GameplaySet::BoxPhysics, GameplaySet::Collisions,
+                GameplaySet::LineClear, GameplaySet::DeathCheck, GameplaySet::Score] {
+        app.configure_sets(FixedUpdate, set.run_if(in_state(RunState::Playing)));
+    }
+    app.add_systems(FixedFirst, advance_tick);
+    app.add_systems(FixedUpdate, (
+        (input_queue::receive_player_input, snapshot_sync::receive_snapshot_acks, input_queue::apply_queued_input).chain().in_set(GameplaySet::Input),
+        (pickups::tick_power_ups, move_players_system).chain().in_set(GameplaySet::Movement),
+        (spawn_fake_box, craning, fall_boxes, pickups::fall_pickups).chain().in_set(GameplaySet::BoxPhysics),
+        (handle_collisions, box_kinds::slide_ice, box_kinds::break_fragile_boxes, box_kinds::explode_bombs, pickups::collect_pickups).chain().in_set(GameplaySet::Collisions),
+        (line_clear::clear_full_rows, line_clear::bomb_lowest_row).chain().in_set(GameplaySet::LineClear),
+        (lives::respawn_players, check_all_dead).chain().in_set(GameplaySet::DeathCheck),
+        (score::award_cleared_rows, score::award_pushes, score::award_survival, director::track_pressure).in_set(GameplaySet::Score),
+        (snapshot_sync::server_network_sync, send_input_acks, pickups::send_power_ups, lives::send_lives).in_set(GameplaySet::Sync),
+    ));
+    app.init_resource::<round::ResultsTimer>();
+    app.add_systems(OnEnter(RunState::GameOver), round::send_results);
+    app.add_systems(Update, round::results_timer.run_if(in_state(RunState::GameOver)).before(round::restart_round));
+    app.add_systems(Startup, setup_timer_and_spawner);
+    app.add_systems(OnEnter(RunState::Playing), (game_rng::start_match_rng, reset_box_spawner, director::reset_director));
+    app
 }
 // GET PLAYER ASSET BASED ON PLAYER COUNT
-fn get_asset() -> String {
-    return if *PLAYER_COUNT.lock().unwrap() == 0 {
-        "running_animation.png".to_string()
-    } else if *PLAYER_COUNT.lock().unwrap() == 1 {
-        "running_animation2.png".to_string()
-    } else if *PLAYER_COUNT.lock().unwrap() == 2 {
-        "running_animation3.png".to_string()
-    } else {
-        "running_animation.png".to_string()
-    }
+fn get_asset(count: usize) -> String {
+    SKINS.get(count).unwrap_or(&SKINS[0]).to_string()
 }
 
-// player ready check
-fn check_if_all_ready(mut server: ResMut<RenetServer>,
+// player ready check, players whose client dropped do not hold up the others
+fn check_if_all_ready(mut server: RoomServer,
                       mut commands: Commands,
-                      mut players: Query<(Entity, &Player, &Transform,&mut PlayerState)>){
+                      players: Query<(Entity, &Player, &Transform,&mut PlayerState), Without<reconnect::Away>>){
     let mut count = 0;
     let mut length = 0;
     for (_entity, _player, _transform, player_state) in players.iter() {
-        if player_state.ready != true{
-            count = count + 1;
+        if !player_state.ready{
+            count += 1;
         }
         length += 1;
     }
@@ -252,305 +324,218 @@ fn check_if_all_ready(mut server: ResMut<RenetServer>,
         server.broadcast_message(ServerChannel::ServerMessages, message);
     }
 }
-fn check_if_should_ready(
-    mut commands: &Commands,
-    mut players: &Query<(Entity, &Player, &Transform,&mut PlayerState)>) -> bool {
-    let mut count = 0;
-    let mut length = 0;
-    for (_entity, _player, _transform, player_state) in players.iter() {
-        if player_state.ready != true{
-            count = count + 1;
-        }
-        length += 1;
-    }
-    if count == 0 {
-       return false
-    }
-    return true
-}
 
-#[allow(clippy::too_many_arguments)]
 // prijma network spravy a kona na zaklade nich
 // take client messages and do stuff based on them
 fn server_update_system(
     mut server_events: EventReader<ServerEvent>,
-    mut commands: Commands,
-    mut lobby: ResMut<ServerLobby>,
-    mut server: ResMut<RenetServer>,
-    mut players: Query<(Entity, &Player, &Transform,&mut PlayerState)>,
-    asset_server: Res<AssetServer>,
-    mut texture_atlas_layouts: ResMut<Assets<TextureAtlasLayout>>,
+    mut handshakes: ResMut<handshake::Handshakes>,
+    mut rooms: NonSendMut<rooms::Rooms>,
+    #[cfg(feature = "transport")] transport: Res<bevy_renet::renet::transport::NetcodeServerTransport>,
 ) {
     for event in server_events.read() {
         match event {
             ServerEvent::ClientConnected { client_id } => {
-                println!("Player {} connected.", client_id);
-                // Initialize other players for this new client
-                for (entity, player, transform, _player_state) in players.iter() {
-                    let translation: [f32; 3] = transform.translation.into();
-                    let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                        id: player.id,
-                        entity,
-                        translation,
-                        asset: player.asset.clone(),
-                    })
-                        .unwrap();
-                    server.send_message(*client_id, ServerChannel::ServerMessages, message);
-                }
-
-                // Spawn new player
-                let transform = Transform::from_translation(Vec3::new(100.0, FLOOR_HEIGHT, 2.0))* Transform::from_scale(Vec3::splat(4.0));
-                let texture = asset_server.load("running_animation.png");
-                let layout = TextureAtlasLayout::from_grid(Vec2::new(24.0,24.0), 7, 1, None, None);
-                let texture_atlas_layout = texture_atlas_layouts.add(layout);
-                let animation_indices = AnimationIndices { first: 0, last: 6 };
-                let player_entity = commands.spawn((
-                    SpriteBundle {
-                        transform, // transform move sprite
-                        // scale sprite
-                        texture,
-                        ..default()
-                    },
-                    TextureAtlas {
-                        layout: texture_atlas_layout,
-                        index: animation_indices.first,
-                    },
-                    // animation_indices,
-                    // AnimationTimer(Timer::from_seconds(0.1, TimerMode::Repeating)),
-                    // Player, // Player tag for queries
-                    PlayerState::default(),
-                    JumpState::default(), // Add JumpState component with default values
-                )).insert(PlayerInput::default())
-                    .insert(Player {
-                        id: *client_id,
-                        asset: get_asset()
-                    })
-                    .id();
-
-                lobby.players.insert(*client_id, player_entity);
-
-                let translation: [f32; 3] = transform.translation.into();
-                let message = bincode::serialize(&ServerMessages::PlayerCreate {
-                    id: *client_id,
-                    entity: player_entity,
-                    translation,
-                    asset: get_asset()
-                })
-                    .unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-                {
-                    let mut count = PLAYER_COUNT.lock().unwrap();
-                    *count += 1;
-                }
+                #[cfg(feature = "transport")]
+                let name = transport.user_data(*client_id)
+                    .map(|user_data| demo_bevy::auth::name_from_user_data(&user_data))
+                    .unwrap_or_default();
+                #[cfg(not(feature = "transport"))]
+                let name = String::new();
+                println!("Player {} ({}) connected, waiting for hello.", client_id, name);
+                // player is spawned once the client proves it runs the same build
+                handshakes.start(*client_id, name);
             }
             ServerEvent::ClientDisconnected { client_id, reason } => {
                 println!("Player {} disconnected: {}", client_id, reason);
-
-                if let Some(player_entity) = lobby.players.remove(client_id) {
-                    commands.entity(player_entity).despawn();
-                }
-
-                let message = bincode::serialize(&ServerMessages::PlayerRemove { id: *client_id }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-            }
-        }
-    }
-    // movement update cita input kanal tam sa posielaju keypress spravy
-    for client_id in server.clients_id() {
-        while let Some(message) = server.receive_message(client_id, ClientChannel::Input) {
-            let input: PlayerInput = bincode::deserialize(&message).unwrap();
-            if input.right{
-                let message = bincode::serialize(&ServerMessages::AnimatePlayer{
-                    id: client_id,
-                    facing_right: true
-                }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-            }
-            else if input.left{
-                let message = bincode::serialize(&ServerMessages::AnimatePlayer{
-                    id: client_id,
-                    facing_right: false
-                }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-            }else{
-                let message = bincode::serialize(&ServerMessages::StopAnimate{
-                    id: client_id
-                }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-            }
-            if input.ready{
-                for (_entity, player, _transform, mut player_state) in players.iter_mut(){
-                    if player.id == client_id{
-                        //println!("player_entered ready");
-                        player_state.ready = true;
-                    }
-                }
-                if check_if_should_ready(&commands, &players){
-                    let message = bincode::serialize(&ServerMessages::YouReady{
-                        id: client_id
-                    }).unwrap();
-                    server.broadcast_message(ServerChannel::ServerMessages, message);
-                }
-            }
-            if let Some(player_entity) = lobby.players.get(&client_id) {
-                commands.entity(*player_entity).insert(input);
+                handshakes.remove(*client_id);
+                rooms::route_disconnect(&mut rooms, *client_id, *reason);
             }
         }
     }
 }
 
-#[allow(clippy::type_complexity)]
-//posiela info o poziciach networked_entities vsetkym hracom
-fn server_network_sync(mut server: ResMut<RenetServer>, players_query: Query<(Entity, &Transform),With<Player>>, boxes_query: Query<(Entity, &Transform),Without<Player>>){ // dangerous second query
-    // najskor hraci
-    let mut networked_entities = NetworkedEntities::default();
-    for (entity, transform) in players_query.iter() {
-        networked_entities.entities.push(entity);
-        networked_entities.translations.push(transform.translation.into());
-        networked_entities.scales.push(transform.scale.x);
-    }
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedEntities, sync_message);
-    // potom boxy
-    let mut networked_entities = NetworkedBoxes::default();
-    for (entity, transform) in boxes_query.iter() {
-        networked_entities.entities.push(entity);
-        networked_entities.translations.push(transform.translation.into());
-    }
-    let sync_message = bincode::serialize(&networked_entities).unwrap();
-    server.broadcast_message(ServerChannel::NetworkedBoxes, sync_message);
-
-
-}
+#[allow(clippy::too_many_arguments)]
+// clients the router sent to this room and the ones that left it
+fn join_players(
+    mut server_events: EventReader<ServerEvent>,
+    mut commands: Commands,
+    mut lobby: ResMut<ServerLobby>,
+    mut server: RoomServer,
+    players: Query<(Entity, &Player, &Transform, &PlayerState)>,
+    away: Query<(Entity, &reconnect::PlayerKey), With<reconnect::Away>>,
+    grace: Res<reconnect::ReconnectGrace>,
+    mut scores: ResMut<score::Scores>,
+    mut welcomed: EventReader<handshake::ClientWelcomed>,
+    mut spectators: ResMut<spectators::Spectators>,
+    max_players: Res<spectators::MaxPlayers>,
+    run_state: Res<State<RunState>>,
+    level: Res<Level>,
+    lives: Res<lives::StartingLives>,
+) {
+    for event in server_events.read() {
+        let ServerEvent::ClientDisconnected { client_id, reason } = event else {
+            continue;
+        };
+        spectators.remove(*client_id);
 
-fn move_players_system(mut server: ResMut<RenetServer>, mut query: Query<(&mut Transform, &PlayerInput,&mut JumpState,&mut PlayerState),With<Player>>, time: Res<Time>) {
-    //println!("dlzka qveriny { }",query.iter().len());
-    for (mut transform, input,mut jump_state,player_state) in query.iter_mut() {
-        //println!("iteracia { }",ff);
-        //ff+= 1;
-        let x = (input.right as i8 - input.left as i8) as f32;
-
-        // To flip player
-        if x > 0.0{
-            transform.scale.x = 4.0;
-        }else if x < 0.0{
-            transform.scale.x = -4.0;
-        }
-        let mut new_player_position_x = transform.translation.x + x * PLAYER_SPEED * time.delta_seconds();
-        if new_player_position_x < LEFT_WALL {
-            new_player_position_x = LEFT_WALL;
-        }
-        else if new_player_position_x > RIGHT_WALL{
-            new_player_position_x = RIGHT_WALL;
+        let Some(player_entity) = lobby.players.remove(client_id) else {
+            continue;
+        };
+        // the player keeps standing there with its score until the client comes back
+        if let Some(away) = grace.away(reason) {
+            println!("Keeping player {} for {:?}.", client_id, grace.0);
+            commands.entity(player_entity).insert((away, PlayerInput::default(), InputQueue::default()));
+            continue;
         }
+        commands.entity(player_entity).despawn();
+        scores.remove_player(*client_id);
 
-        if new_player_position_x > transform.translation.x{
-            if player_state.oprety_zprava == true{
-                new_player_position_x = transform.translation.x;
+        let message = bincode::serialize(&ServerMessages::PlayerRemove { id: *client_id }).unwrap();
+        server.broadcast_message(ServerChannel::ServerMessages, message);
+    }
+    for handshake::ClientWelcomed { client_id, name, player_key, .. } in welcomed.read() {
+        let name = name.clone();
+        let reclaimed = away.iter().find(|(_, key)| key.0 == *player_key).map(|(entity, _)| entity);
+        // the rest of the world is sent by join_sync once this player exists
+        if let Some((entity, player, transform, player_state)) = reclaimed.and_then(|entity| players.get(entity).ok()) {
+            println!("Player {} is back as {}.", player.id, client_id);
+            commands.entity(entity)
+                .remove::<reconnect::Away>()
+                .insert(Player { id: *client_id, asset: player.asset.clone(), name: player.name.clone() });
+            lobby.players.insert(*client_id, entity);
+            scores.rename_player(player.id, *client_id);
+            // other clients know it under the old id
+            let message = bincode::serialize(&ServerMessages::PlayerRemove { id: player.id }).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages, message);
+            if !player_state.dead {
+                let message = bincode::serialize(&ServerMessages::PlayerCreate {
+                    id: *client_id,
+                    entity,
+                    translation: transform.translation.into(),
+                    asset: player.asset.clone(),
+                    name: player.name.clone(),
+                }).unwrap();
+                server.broadcast_message(ServerChannel::ServerMessages, message);
             }
+            continue;
         }
-        if new_player_position_x < transform.translation.x{
-            if player_state.oprety_zlava == true{
-                new_player_position_x = transform.translation.x;
-            }
+
+        // mid-match or full, the client watches until a place frees up
+        if *run_state.get() != RunState::Waiting || lobby.players.len() + away.iter().count() >= max_players.0 {
+            println!("Player {} ({}) joined as a spectator.", client_id, name);
+            spectators.add(*client_id, name, *player_key);
+            continue;
         }
+        spawn_player(&mut commands, &mut server, &mut lobby, &mut scores, &level, lives.0, *client_id, name, *player_key);
+    }
+}
 
-        // toto je nechutne prepojene s collision checkingom ale co uz
-        if player_state.oprety_zdola == true{  // is not standing on something
+// Spawn new player
+#[allow(clippy::too_many_arguments)]
+fn spawn_player(commands: &mut Commands, server: &mut RoomServer, lobby: &mut ServerLobby, scores: &mut score::Scores,
+                level: &Level, lives: u32, client_id: ClientId, name: String, player_key: u64) {
+    let transform = spawn_transform(level, lobby.players.len());
+    let asset = get_asset(lobby.players.len());
+    let player_entity = commands.spawn((
+        transform, // scale.x is sent to clients to flip the sprite
+        PLAYER_COLLIDER,
+        PlayerState::default(),
+        JumpState::default(), // Add JumpState component with default values
+    )).insert((PlayerInput::default(), InputQueue::default(), PowerUps::default(), Lives::new(lives)))
+        .insert(Player {
+            id: client_id,
+            asset: asset.clone(),
+            name: name.clone(),
+        })
+        .insert(reconnect::PlayerKey(player_key))
+        .id();
+
+    lobby.players.insert(client_id, player_entity);
+    scores.add_player(client_id);
+
+    let translation: [f32; 3] = transform.translation.into();
+    let message = bincode::serialize(&ServerMessages::PlayerCreate {
+        id: client_id,
+        entity: player_entity,
+        translation,
+        asset,
+        name,
+    })
+        .unwrap();
+    server.broadcast_message(ServerChannel::ServerMessages, message);
+}
 
-            jump_state.is_jumping = false;
-            jump_state.floor_reset = true;
-        }
-        else if jump_state.is_jumping == false{ // is not in the jump phase
-            transform.translation.y -= time.delta_seconds()*PLAYER_SPEED;
-        }
+fn advance_tick(mut tick: ResMut<SimulationTick>) {
+    tick.0 += 1;
+}
 
-        transform.translation.x = new_player_position_x;
-        // teraz y suradnicu riesime:
-        if input.up && !jump_state.is_jumping &&  jump_state.can_jump && jump_state.floor_reset {
-            //START JUMP WHEN PRESSED checking jump_state so doesnt jump again when pressing
-            if player_state.dead == false{
-                let message = bincode::serialize(&ServerMessages::SoundAction{
-                    sound: 1
-                }).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages, message);
-            }
-            jump_state.is_jumping = true;
-            jump_state.floor_reset = false;
-            jump_state.can_jump = false;
-            jump_state.jump_timer.reset();
-            jump_state.fall_timer.reset();
-        }
-        if jump_state.is_jumping {
-            // Jumping up
-            if !jump_state.jump_timer.finished(){
-                transform.translation.y += JUMP_VELOCITY * time.delta_seconds();
-            }
-            //Jumping down when up is finished
-            if jump_state.jump_timer.finished() {
-                transform.translation.y -= JUMP_VELOCITY * time.delta_seconds();
-            }
-            //Tick timers and check finish
-            jump_state.jump_timer.tick(time.delta());
-            jump_state.fall_timer.tick(time.delta());
-            if jump_state.fall_timer.finished(){
-                jump_state.is_jumping = false;
-            }
-        }
+// kazdy hrac dostane stav svojej postavy po poslednom inpute
+#[allow(clippy::type_complexity)]
+fn send_input_acks(mut server: RoomServer, tick: Res<SimulationTick>,
+                   players: Query<(&Player, &Transform, &JumpState, &PlayerState, &InputQueue, &PowerUps), Without<reconnect::Away>>){
+    for (player, transform, jump_state, player_state, queue, power_ups) in players.iter() {
+        let ack = InputAck {
+            tick: tick.0,
+            sequence: queue.last_sequence,
+            translation: transform.translation.into(),
+            scale: transform.scale.x,
+            jump: jump_state.into(),
+            contacts: player_state.contacts(),
+            boosts: power_ups.boosts(),
+        };
+        server.send_message(player.id, ServerChannel::InputAck, bincode::serialize(&ack).unwrap());
     }
-    for (_, _, mut jump_state, _) in query.iter_mut() {
-        if jump_state.floor_reset {
-            jump_state.can_jump = true;
+}
+
+// the rules are in demo_bevy::movement, client predicts its own player with the same code
+#[allow(clippy::type_complexity)]
+fn move_players_system(mut server: RoomServer, mut query: Query<(&mut Transform, &PlayerInput,&mut JumpState,&PlayerState,&PowerUps),With<Player>>, time: Res<Time>, level: Res<Level>) {
+    for (mut transform, input, mut jump_state, player_state, power_ups) in query.iter_mut() {
+        let jumped = move_player(&mut transform, input, &mut jump_state, player_state.contacts(), power_ups.boosts(), &level.arena, time.delta());
+        if jumped && !player_state.dead{
+            let message = bincode::serialize(&ServerMessages::SoundAction{
+                sound: 1
+            }).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages, message);
         }
     }
 }
 
 
 
+// same first spawn every match so the seed replays it
+fn reset_box_spawner(mut spawner: ResMut<BoxSpawner>) {
+    spawner.timer = Timer::new(Duration::from_secs(5), TimerMode::Repeating);
+}
+
 pub fn setup_timer_and_spawner(mut commands: Commands) {
-    commands.spawn(( // Treba tam kameru lebo potom hadze warningy aj ked nema okno
-        Camera2dBundle {
-            transform: Transform::from_xyz(100.0, 200.0, 0.0),
-            ..default()
-        },
-    ));
     commands.insert_resource(BoxSpawner {
         // create the repeating timer
         timer: Timer::new(Duration::from_secs(5), TimerMode::Repeating),
     });
-    commands.insert_resource(game_over::GameOverTimer {
-        // create the repeating timer
-        timer: Timer::new(Duration::from_secs(5), TimerMode::Repeating),
-        second_timer:  Timer::new(Duration::from_secs(10), TimerMode::Repeating),
-    });
 }
 
 // ovlada boxy ked su este fake boxy (cestuju ma zeriave do stran)
-fn craning(mut query: Query<(&mut Transform,Entity,&mut FakeboxState), With<FakeBox>>,time: Res<Time>,mut commands: Commands,
-           asset_server: Res<AssetServer>, mut server: ResMut<RenetServer>,){
-    for (mut transform,entity, fakebox_state) in &mut query.iter_mut(){  // pre kazdu
+#[allow(clippy::type_complexity)]
+fn craning(mut query: Query<(&mut Transform,Entity,&mut FakeboxState,Option<&BoxKind>,Option<&PickupKind>), With<FakeBox>>,time: Res<Time>,mut commands: Commands,
+           mut server: RoomServer, level: Res<Level>, director: Res<director::Director>){
+    for (mut transform,entity, fakebox_state, kind, pickup) in &mut query.iter_mut(){  // pre kazdu
+        let predtym = transform.translation.x;
         if fakebox_state.smer_doprava{
-            transform.translation.x =  transform.translation.x + BOX_SPEED * time.delta_seconds();
+            transform.translation.x += director.crane_speed() * time.delta_seconds();
         }
         else{
-            transform.translation.x =  transform.translation.x - BOX_SPEED * time.delta_seconds();
+            transform.translation.x -= director.crane_speed() * time.delta_seconds();
         }
 
 
         // spravi realny box ak je na spawn pozicii
 
-        if ((BOX_SPAWNS[fakebox_state.index_padnutia] - transform.translation.x.round()).abs()) < 10.0 { // tricky porovnavanie floatov
-            let novy_box_transform: Transform;
-            novy_box_transform = Transform::from_translation(Vec3::new(BOX_SPAWNS[fakebox_state.index_padnutia], 520.0, 2.0)) // transform move sprite
-                * Transform::from_scale(Vec3::splat(3.0));
+        let column = level.columns[fakebox_state.index_padnutia];
+        if director::reached_column(predtym, transform.translation.x, column) {
+            let novy_box_transform = Transform::from_translation(Vec3::new(column, level.crane.drop_height, 2.0));
             let novy_translation: [f32; 3] = novy_box_transform.translation.into();
-            let novy_box = commands.spawn((SpriteBundle{
-                transform: novy_box_transform,
-                texture: asset_server.load("box.png"),
-                ..default()
-            },
-                                               Box,
-                                               BoxState::default())).id();
             // odstrani fakovy box
             let despawn_message = ServerMessages::DespawnBox {entity_to_despawn:entity};
             let despawn_message = bincode::serialize(&despawn_message).unwrap();
@@ -558,9 +543,13 @@ fn craning(mut query: Query<(&mut Transform,Entity,&mut FakeboxState), With<Fake
             commands.entity(entity).despawn();
 
             // aby vsetci clienti spravili tuto entitu u seba
-            let message = ServerMessages::SpawnBox {
-                entity: novy_box,
-                translation: novy_translation,
+            let message = if let Some(pickup) = pickup {
+                let novy_pickup = commands.spawn((novy_box_transform, pickups::PICKUP_COLLIDER, pickups::Pickup::default(), *pickup)).id();
+                ServerMessages::SpawnPickup { entity: novy_pickup, translation: novy_translation, kind: *pickup }
+            } else {
+                let kind = kind.copied().unwrap_or_default();
+                let novy_box = commands.spawn((novy_box_transform, BOX_COLLIDER, Box, BoxState::default(), kind)).id();
+                ServerMessages::SpawnBox { entity: novy_box, translation: novy_translation, kind }
             };
             let message = bincode::serialize(&message).unwrap();
             server.broadcast_message(ServerChannel::ServerMessages,message);
@@ -571,306 +560,265 @@ fn craning(mut query: Query<(&mut Transform,Entity,&mut FakeboxState), With<Fake
 
 
 
-fn spawn_fake_box(mut server: ResMut<RenetServer>, mut commands: Commands, time: Res<Time>, mut spawn_timer: ResMut<BoxSpawner>, asset_server: Res<AssetServer>, ){
+fn spawn_fake_box(mut server: RoomServer, mut commands: Commands, time: Res<Time>, mut spawn_timer: ResMut<BoxSpawner>, mut rng: ResMut<game_rng::GameRng>, level: Res<Level>,
+                  director: Res<director::Director>){
 
 
     spawn_timer.timer.tick(time.delta());
                                                                  // TOTO NA TESTING ABY SA NESPAWNOVALI STALE BOXI
     if spawn_timer.timer.finished()   { // To start spawning only when player is connected  mozme nastavit asi potom na 2-3 ako chceme
-        let num = thread_rng().gen_range(1..8);
-        let mut rng = thread_rng();
+        let interval = director.spawn_interval(&mut **rng);
         let bul =  rng.gen_bool(1.0 / 2.0);  // sanca 1/2
 
-        spawn_timer.timer.set_duration(Duration::from_secs(num));
-        let random_index = thread_rng().gen_range(0..NUM_OF_BOXES-1);
-        println!("{} spawned", random_index);
-
-        let novy_box;
-        let novy_translation;
-        if bul == false{
-            novy_translation = [-500.0, 500.0, 2.0];
-            novy_box = commands.spawn((SpriteBundle{
-                transform: Transform::from_translation(Vec3::new(-500.0, 500.0, 2.0)) // transform move sprite
-                ,
-                texture: asset_server.load("box2.png"),
-                ..default()
-            },
-                                       FakeBox,FakeboxState{smer_doprava:true,index_padnutia:random_index},
-            )).id();
-        }
-        else{
-            novy_translation = [600.0, 500.0, 2.0];
-            novy_box = commands.spawn((SpriteBundle{
-                transform: Transform::from_translation(Vec3::new(600.0, 500.0, 2.0)) // transform move sprite
-                ,
-                texture: asset_server.load("box2.png"),
-                ..default()
-            },
-                                       FakeBox,FakeboxState{smer_doprava:false,index_padnutia:random_index},
-            )).id();
+        spawn_timer.timer.set_duration(interval);
+        // every crane takes its load to another column, they come from both sides in turns
+        let indexes = director.drop_columns(&mut **rng, level.columns.len());
+        for (crane, random_index) in indexes.into_iter().enumerate() {
+            let bul = bul != (crane % 2 == 1);
+            let kind = box_kinds::roll_kind(&mut **rng);
+            let pickup = pickups::roll_pickup(&mut **rng);
+
+            let novy_translation = if !bul {
+                [level.crane.left, level.crane.height, 2.0]
+            } else {
+                [level.crane.right, level.crane.height, 2.0]
+            };
+            let mut novy_box = commands.spawn((
+                Transform::from_translation(novy_translation.into()),
+                FakeBox,FakeboxState{smer_doprava:!bul,index_padnutia:random_index},
+            ));
+            // the crane carries a box or a pickup
+            let message = match pickup {
+                Some(pickup) => {
+                    novy_box.insert(pickup);
+                    ServerMessages::SpawnPickup { entity: novy_box.id(), translation: novy_translation, kind: pickup }
+                }
+                None => {
+                    novy_box.insert(kind);
+                    ServerMessages::SpawnBox { entity: novy_box.id(), translation: novy_translation, kind }
+                }
+            };
+            let message = bincode::serialize(&message).unwrap();
+            server.broadcast_message(ServerChannel::ServerMessages,message);
         }
-        let message = ServerMessages::SpawnBox {
-            entity: novy_box,
-            translation: novy_translation,
-        };
-        let message = bincode::serialize(&message).unwrap();
-        server.broadcast_message(ServerChannel::ServerMessages,message);
-        increment_box(); // TESTING
     }
 }
 
-fn fall_boxes(_server: ResMut<RenetServer>, mut query: Query<(&mut Transform, &mut BoxState), With<Box>>,time: Res<Time>,){
+fn fall_boxes(_server: RoomServer, mut query: Query<(&mut Transform, &mut BoxState), With<Box>>,time: Res<Time>,){
     for (mut transform, box_state) in &mut query.iter_mut(){
-        if box_state.oprety_zdola == false{
-            transform.translation.y =  transform.translation.y - BOX_SPEED * time.delta_seconds();
+        if !box_state.oprety_zdola{
+            transform.translation.y -= BOX_SPEED * time.delta_seconds();
         }
     }
 }
-pub fn despawn_boxes(mut server: ResMut<RenetServer>, mut commands: Commands, mut boxy: Query<(&mut Transform,&mut BoxState, Entity), With<Box>>){
-    //checkovat v loop ci je neajky riadok zaplneny cely ten ze sa tam uz nemesti dalsi box
-    // potom len posielat spravy o despawnovani boxov aj tu despawnut
-    struct Point {
-        x: f32,
-        y: f32,
-    }
-    let mut all_points: Vec<Point> = Vec::with_capacity(100);
-    let mut levels: Vec<f32> = Vec::with_capacity(10);
-    // Iterate through boxes to collect their positions
-    for (transform, box_state, _) in boxy.iter_mut() {
-        if box_state.oprety_zdola == true{
-            all_points.push(Point {
-                x: transform.translation.x,
-                y: transform.translation.y
-            });
-        }
-    }
-    //Get all levels boxes
-    for point in &all_points {
-        if !levels.contains(&point.y) {
-            levels.push(point.y);
-        }
-    }
-    //println!("{}", levels.len());
-    let left: f32 = -360.0;
-    let right: f32 = 550.0;
-    for level in levels{
-        let mut space_available = true;
-
-        // Sort boxes on this level by their x position
-        let mut boxes_on_level: Vec<&Point> = all_points.iter()
-            .filter(|point| point.y == level)
-            .collect();
-        boxes_on_level.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
-        let mut string = String::new();
-        for i in 0..(boxes_on_level.len() - 1) {
-            string.push_str(&format!("{} ", i));
-        }
-        println!("{}", string);
-        for i in 0..(boxes_on_level.len() - 1) {
-            let box_right_edge = boxes_on_level[i].x + 71.0;
-            let next_box_left_edge = boxes_on_level[i + 1].x;
-            if next_box_left_edge - box_right_edge < 71.0 {
-                // There's not enough space between boxes on this level to fit another box
-                //space_available = false;
-                break;
-            }
-        }
-        if space_available == false{
-            for (_, _, mut entity) in boxy.iter_mut() {
-                let message = ServerMessages::DespawnBox {
-                    entity_to_despawn: entity
-                };
-                let message = bincode::serialize(&message).unwrap();
-                server.broadcast_message(ServerChannel::ServerMessages,message);
-                commands.entity(entity).despawn();
-            }
-        }
-    }
-
-    /*let message = ServerMessages::DespawnBox {
-        entity_to_despawn:
-    }
-    let message = bincode::serialize(&message).unwrap();
-    server.broadcast_message(ServerChannel::ServerMessages,message);*/
-}
-
+#[allow(clippy::type_complexity)]
 fn handle_collisions(
     mut commands: Commands,
-    mut server: ResMut<RenetServer>,
-    mut player: Query<(&mut Transform, &mut PlayerState, &mut JumpState, &mut Player, Entity)>,
-    mut boxy: Query<(&mut Transform,&mut BoxState), (With<Box>,Without<Player>)>,
+    mut server: RoomServer,
+    mut player: Query<(&mut Transform, &Collider, &mut PlayerState, &JumpState, &Player, &mut PowerUps, &mut Lives)>,
+    mut boxy: Query<(Entity, &mut Transform, &Collider, &mut BoxState, &BoxKind), (With<Box>,Without<Player>)>,
+    mut pushed: EventWriter<score::BoxPushed>,
+    level: Res<Level>,
     //mut run_state: ResMut<State<RunState>>,
 )
 {
     // toto su iba kolizie medzi boxami
     // reset bot zmena a oprety zhora
-    for ( _transform,mut box_state)in &mut boxy.iter_mut(){
+    for (_entity, _transform, _collider, mut box_state, _kind)in &mut boxy.iter_mut(){
         box_state.bot_zmena = false;
         box_state.oprety_zhora = false;
+        box_state.landed_on = false;
     }
     // check collisions
     let mut combos = boxy.iter_combinations_mut();
-    while let Some([( mut trans_1,mut box_state_1), (mut trans_2, mut box_state_2)]) = combos.fetch_next(){
-        let a = collision_detection::AABB{
-            min: (trans_1.translation.x + 1.0, trans_1.translation.y),
-            max: (trans_1.translation.x + BOX_SIZE - 1.0, trans_1.translation.y + BOX_SIZE)
-        };
-        let b = collision_detection::AABB{
-            min: (trans_2.translation.x + 1.0, trans_2.translation.y),
-            max: (trans_2.translation.x + BOX_SIZE - 1.0, trans_2.translation.y + BOX_SIZE)
-        };
+    while let Some([(_, trans_1, collider_1, mut box_state_1, _), (_, mut trans_2, collider_2, mut box_state_2, _)]) = combos.fetch_next(){
+        let a = collider_1.aabb(trans_1.translation);
+        let b = collider_2.aabb(trans_2.translation);
         let result = collision_detection::test_aabb_overlap(a, b); // Returns depth_y depth_x
         // CHECK ON WHICH SIDE THE COLLISION HAPPENED
-        match result{
-            Some((depth_x, depth_y)) => {
-                if depth_x.abs() < depth_y.abs() {
-                    // Collision along the X axis. React accordingly
-                    if depth_x > 0.0 {
-                        box_state_1._oprety_zlava = true;
-                        box_state_2._oprety_sprava = true;
-                        //trans_1.translation.x = trans_2.translation.x + BOX_SIZE;
-                    } else {
-                        //PRAVO
-                        box_state_2._oprety_zlava = true;
-                        box_state_1._oprety_sprava = true;
-                        //trans_2.translation.x = trans_1.translation.x + BOX_SIZE;
-                    }
+        if let Some((depth_x, depth_y)) = result {
+            if depth_x.abs() < depth_y.abs() {
+                // Collision along the X axis. React accordingly
+                if depth_x > 0.0 {
+                    box_state_1._oprety_zlava = true;
+                    box_state_2._oprety_sprava = true;
+                    //trans_1.translation.x = trans_2.translation.x + BOX_SIZE;
                 } else {
-                    // Collision along the Y axis.
-                    if depth_y > 0.0 {
-                        // Top side collision
-                    } else  if depth_y < 0.0{
-                        // Box 2 fell on Box 1 send message with audio and set state
-                        box_state_2.bot_zmena = true;
-                        if box_state_2.oprety_zdola == false{
-                            let message = bincode::serialize(&ServerMessages::SoundAction{
-                                sound: 2
-                            }).unwrap();
-                            server.broadcast_message(ServerChannel::ServerMessages, message);
-                        }
-                        trans_2.translation.y = trans_1.translation.y + BOX_SIZE - 1.0;
-                        box_state_2.oprety_zdola = true;
-                        box_state_1.oprety_zhora = true;
+                    //PRAVO
+                    box_state_2._oprety_zlava = true;
+                    box_state_1._oprety_sprava = true;
+                    //trans_2.translation.x = trans_1.translation.x + BOX_SIZE;
+                }
+            } else {
+                // Collision along the Y axis.
+                if depth_y > 0.0 {
+                    // Top side collision
+                } else  if depth_y < 0.0{
+                    // Box 2 fell on Box 1 send message with audio and set state
+                    box_state_2.bot_zmena = true;
+                    if !box_state_2.oprety_zdola{
+                        let message = bincode::serialize(&ServerMessages::SoundAction{
+                            sound: 2
+                        }).unwrap();
+                        server.broadcast_message(ServerChannel::ServerMessages, message);
+                        box_state_1.landed_on = true;
                     }
+                    trans_2.translation.y = trans_1.translation.y + BOX_SIZE - 1.0;
+                    box_state_2.oprety_zdola = true;
+                    box_state_1.oprety_zhora = true;
                 }
-            }, _ => {}
+            }
         }
     }
     // Check if some box is in collision with ground send audio message if yes amd set state
-    for ( mut transform,mut box_state)in &mut boxy.iter_mut(){
-        if transform.translation.y <= FLOOR_HEIGHT - 3.0{
+    for (_entity, mut transform, _collider, mut box_state, _kind)in &mut boxy.iter_mut(){
+        if transform.translation.y <= box_floor(&level){
             box_state.bot_zmena = true;
-            if box_state.oprety_zdola == false{
+            if !box_state.oprety_zdola{
                 let message = bincode::serialize(&ServerMessages::SoundAction{
                     sound: 2
                 }).unwrap();
                 server.broadcast_message(ServerChannel::ServerMessages, message);
             }
             box_state.oprety_zdola = true;
-            transform.translation.y = FLOOR_HEIGHT - 3.0;
+            transform.translation.y = box_floor(&level);
         }
     }
     // Check if bot collision with box or ground if not reset oprety_zdola
-    for ( _transform,mut box_state)in &mut boxy.iter_mut(){
-        if box_state.bot_zmena == false{
+    for (_entity, _transform, _collider, mut box_state, _kind)in &mut boxy.iter_mut(){
+        if !box_state.bot_zmena{
             box_state.oprety_zdola = false;
         }
     }
     // Collisions player boxes
-    for (mut player_transform, mut player_state, jump_state,mut  player, entity) in &mut player.iter_mut(){
+    let mut heavy_pushes = box_kinds::HeavyPushes::default();
+    for (mut player_transform, player_collider, mut player_state, jump_state, player, mut power_ups, mut lives) in &mut player.iter_mut(){
         player_state.oprety_zprava  = false;
         player_state.oprety_zlava = false;
         let mut bot = false;
-        if player_state.dead != true {
-            for (mut box_transform, mut _box_state) in boxy.iter_mut() {
-                let a = collision_detection::AABB {
-                    min: (player_transform.translation.x + 5.0, player_transform.translation.y),
-                    max: (player_transform.translation.x + 75.0, player_transform.translation.y + 70.0)
-                };
-                let b = collision_detection::AABB {
-                    min: (box_transform.translation.x + 1.0, box_transform.translation.y),
-                    max: (box_transform.translation.x + BOX_SIZE - 1.0, box_transform.translation.y + BOX_SIZE)
-                };
+        if !player_state.dead {
+            for (box_entity, mut box_transform, box_collider, mut _box_state, kind) in boxy.iter_mut() {
+                // one box is enough, the rest would take more lives in the same tick
+                if player_state.dead {
+                    break;
+                }
+                let a = player_collider.aabb(player_transform.translation);
+                let b = box_collider.aabb(box_transform.translation);
                 let result = collision_detection::test_aabb_overlap(a, b); // Returns depth_y depth_x
                 // CHECK ON WHICH SIDE THE COLLISION HAPPENED
-                match result {
-                    Some((depth_x, depth_y)) => {
-                        if depth_x.abs() < depth_y.abs() {
-                            // Collision along the X axis. React accordingly
-                            if depth_x > 0.0 {
-                                //  Player collision left -> pushing box left
-                                player_state.oprety_zlava = true;
-                                if _box_state._oprety_zlava != true && jump_state.is_jumping == false && _box_state.oprety_zhora != true {
-                                    let mut new_x = box_transform.translation.x - PUSH_SPEED;
-                                    if new_x <= MOST_LEFT_BOX { //check most_left spawn
-                                        new_x = MOST_LEFT_BOX
-                                    }
-                                    box_transform.translation.x = new_x;
+                if let Some((depth_x, depth_y)) = result {
+                    if depth_x.abs() < depth_y.abs() {
+                        // Collision along the X axis. React accordingly
+                        if depth_x > 0.0 {
+                            //  Player collision left -> pushing box left
+                            player_state.oprety_zlava = true;
+                            if !_box_state._oprety_zlava && !jump_state.is_jumping && !_box_state.oprety_zhora {
+                                if *kind == BoxKind::Heavy {
+                                    heavy_pushes.add(box_entity, -1, player.id);
+                                    continue;
+                                }
+                                if *kind == BoxKind::Ice {
+                                    _box_state.slide = -1.0;
                                 }
-                            } else {
-                                //  Player collision right -> pushing box right
-                                player_state.oprety_zprava = true;
-                                if _box_state._oprety_sprava != true && jump_state.is_jumping == false && _box_state.oprety_zhora != true {
-                                    let mut new_x = box_transform.translation.x + PUSH_SPEED;
-                                    if new_x >= MOST_RIGHT_BOX { // check most_right spawn
-                                        new_x = MOST_RIGHT_BOX
-                                    }
-                                    box_transform.translation.x = new_x;
+                                let mut new_x = box_transform.translation.x - PUSH_SPEED;
+                                if new_x <= level.leftmost_column() { //check most_left spawn
+                                    new_x = level.leftmost_column()
                                 }
+                                pushed.send(score::BoxPushed { id: player.id, distance: box_transform.translation.x - new_x });
+                                box_transform.translation.x = new_x;
                             }
                         } else {
-                            // Collision along the Y axis.
-                            if depth_y > 0.0 {
-                                bot = true;
-                                // Top side collision
-                                // Player bot collision with top of box so jump on box
-                                if player_state.oprety_zdola == false {
-                                    player_transform.translation.y = box_transform.translation.y + BOX_SIZE - 1.0;
+                            //  Player collision right -> pushing box right
+                            player_state.oprety_zprava = true;
+                            if !_box_state._oprety_sprava && !jump_state.is_jumping && !_box_state.oprety_zhora {
+                                if *kind == BoxKind::Heavy {
+                                    heavy_pushes.add(box_entity, 1, player.id);
+                                    continue;
+                                }
+                                if *kind == BoxKind::Ice {
+                                    _box_state.slide = 1.0;
                                 }
-                                player_state.oprety_zdola = true;
-                            } else {
-                                if _box_state.oprety_zdola == false {
-                                    // PLAYER DEAD
-                                    let message = bincode::serialize(&ServerMessages::SoundAction{
-                                        sound: 4
-                                    }).unwrap();
-                                    server.broadcast_message(ServerChannel::ServerMessages, message);
-                                    let message = bincode::serialize(&ServerMessages::YouDead {
-                                        id: player.id
-                                    }).unwrap();
-                                    server.broadcast_message(ServerChannel::ServerMessages, message);
-                                    player_state.dead = true;
-                                    let message = bincode::serialize(&ServerMessages::PlayerRemove { id: player.id }).unwrap();
-                                    server.broadcast_message(ServerChannel::ServerMessages, message);
+                                let mut new_x = box_transform.translation.x + PUSH_SPEED;
+                                if new_x >= level.rightmost_column() { // check most_right spawn
+                                    new_x = level.rightmost_column()
                                 }
+                                pushed.send(score::BoxPushed { id: player.id, distance: new_x - box_transform.translation.x });
+                                box_transform.translation.x = new_x;
                             }
                         }
-                    },
-                    _ => {}
+                    } else {
+                        // Collision along the Y axis.
+                        if depth_y > 0.0 {
+                            bot = true;
+                            // Top side collision
+                            // Player bot collision with top of box so jump on box
+                            if !player_state.oprety_zdola {
+                                player_transform.translation.y = box_transform.translation.y + BOX_SIZE - 1.0;
+                                _box_state.landed_on = true;
+                            }
+                            player_state.oprety_zdola = true;
+                        } else if !_box_state.oprety_zdola && !lives.invulnerable() {
+                            if power_ups.take_hit() {
+                                // shield, the box breaks on the player
+                                let message = bincode::serialize(&ServerMessages::DespawnBox { entity_to_despawn: box_entity }).unwrap();
+                                server.broadcast_message(ServerChannel::ServerMessages, message);
+                                commands.entity(box_entity).despawn();
+                                continue;
+                            }
+                            let message = bincode::serialize(&ServerMessages::SoundAction{
+                                sound: 4
+                            }).unwrap();
+                            server.broadcast_message(ServerChannel::ServerMessages, message);
+                            // with a life left it is off the field until lives::respawn_players brings it back
+                            if !lives.lose() {
+                                // PLAYER DEAD
+                                let message = bincode::serialize(&ServerMessages::YouDead {
+                                    id: player.id
+                                }).unwrap();
+                                server.broadcast_message(ServerChannel::ServerMessages, message);
+                            }
+                            player_state.dead = true;
+                            let message = bincode::serialize(&ServerMessages::PlayerRemove { id: player.id }).unwrap();
+                            server.broadcast_message(ServerChannel::ServerMessages, message);
+                        }
+                    }
                 }
             }
         }
         // Check ground collision
-        if player_transform.translation.y <= FLOOR_HEIGHT{
+        if player_transform.translation.y <= level.arena.floor{
             bot = true;
             player_state.oprety_zdola = true;
-            player_transform.translation.y = FLOOR_HEIGHT;
+            player_transform.translation.y = level.arena.floor;
         }
         // If collision with ground or box not detected reset oprety_zdola
-        if bot == false{
+        if !bot{
             player_state.oprety_zdola = false
         }
     }
+    // heavy boxes move only when enough players push them the same way
+    for (entity, direction, pushers) in heavy_pushes.moved() {
+        let Ok((_, mut box_transform, _, _, _)) = boxy.get_mut(entity) else {
+            continue;
+        };
+        let new_x = (box_transform.translation.x + direction as f32 * PUSH_SPEED).clamp(level.leftmost_column(), level.rightmost_column());
+        let distance = (new_x - box_transform.translation.x).abs();
+        box_transform.translation.x = new_x;
+        for id in pushers {
+            pushed.send(score::BoxPushed { id, distance });
+        }
+    }
 }
 
-fn check_all_dead(mut server: ResMut<RenetServer>,
+// players waiting for a respawn are still in the game
+fn check_all_dead(mut server: RoomServer,
                   mut commands: Commands,
-                  mut players: Query<(Entity, &Player, &Transform,&mut PlayerState)>){
+                  players: Query<(Entity, &Player, &PlayerState, &Lives)>){
     let mut count = 0;
     let mut length = 0;
-    for (_entity, _player, _transform, player_state) in players.iter() {
-        if player_state.dead != true{
-            count = count + 1;
+    for (_entity, _player, player_state, lives) in players.iter() {
+        if !player_state.dead || lives.respawning(){
+            count += 1;
         }
         length += 1;
     }
@@ -881,4 +829,86 @@ fn check_all_dead(mut server: ResMut<RenetServer>,
         }).unwrap();
         server.broadcast_message(ServerChannel::ServerMessages, message);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+    use bevy_renet::renet::RenetClient;
+    use demo_bevy::snapshot::{SnapshotHistory, WorldSnapshot, POSITION_GRID};
+    use demo_bevy::{connection_config, SequencedInput};
+
+    const TICK_RATE: f64 = 10.0;
+    const SETS: [GameplaySet; 8] = [GameplaySet::Input, GameplaySet::Movement, GameplaySet::BoxPhysics, GameplaySet::Collisions,
+        GameplaySet::LineClear, GameplaySet::DeathCheck, GameplaySet::Score, GameplaySet::Sync];
+
+    // sets in the order they ran
+    #[derive(Resource, Default)]
+    struct SetsRun(Vec<GameplaySet>);
+
+    fn record(set: GameplaySet) -> impl FnMut(ResMut<SetsRun>) {
+        move |mut sets_run: ResMut<SetsRun>| sets_run.0.push(set)
+    }
+
+    #[test]
+    fn one_tick_applies_input_moves_collides_and_syncs_in_order() {
+        let config = rooms::RoomConfig {
+            tick_rate: TICK_RATE,
+            seed: Some(1),
+            net_stats: false,
+            reconnect_grace: 0.0,
+            max_players: SKINS.len(),
+            level: Level::default(),
+            lives: 3,
+        };
+        let mut app = room_app(&config);
+        let client_id = ClientId::from_raw(1);
+        let mut server = RenetServer::new(connection_config());
+        server.add_connection(client_id);
+        app.insert_resource(server)
+            .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / TICK_RATE)))
+            .init_resource::<SetsRun>();
+        for set in SETS {
+            app.add_systems(FixedUpdate, record(set).in_set(set));
+        }
+        app.finish();
+        app.cleanup();
+        app.world.resource_mut::<rooms::RoomMembers>().0.insert(client_id);
+        // joins while waiting, then the match starts
+        app.world.send_event(handshake::ClientWelcomed { client_id, name: String::new(), player_key: 0, room: String::new() });
+        app.update();
+        app.world.resource_mut::<NextState<RunState>>().set(RunState::Playing);
+        app.update();
+
+        let entity = app.world.resource::<ServerLobby>().players[&client_id];
+        let start = app.world.get::<Transform>(entity).unwrap().translation.x;
+        let tick = app.world.resource::<SimulationTick>().0;
+        app.world.resource_mut::<SetsRun>().0.clear();
+        app.world.get_mut::<InputQueue>(entity).unwrap().inputs
+            .push_back(SequencedInput { sequence: 1, input: PlayerInput { right: true, ..default() } });
+        app.update();
+
+        // exactly one tick, every set once and in order
+        assert_eq!(app.world.resource::<SimulationTick>().0, tick + 1);
+        assert_eq!(app.world.resource::<SetsRun>().0, SETS);
+        // the input queued for this tick moved the player in the same tick
+        assert!(app.world.get::<PlayerInput>(entity).unwrap().right);
+        let x = app.world.get::<Transform>(entity).unwrap().translation.x;
+        assert!(x > start, "{} > {}", x, start);
+
+        // the last snapshot is of this tick and has the player where movement and collisions left it
+        let mut client = RenetClient::new(connection_config());
+        for packet in app.world.resource_mut::<RenetServer>().get_packets_to_send(client_id).unwrap() {
+            client.process_packet(&packet);
+        }
+        let mut last = None;
+        while let Some(message) = client.receive_message(ServerChannel::NetworkedEntities) {
+            last = Some(bincode::deserialize::<WorldSnapshot>(&message).unwrap());
+        }
+        let snapshot = last.unwrap();
+        assert_eq!(snapshot.tick, tick + 1);
+        let state = snapshot.decode(&SnapshotHistory::default()).unwrap();
+        assert!((state[&entity.to_bits()].translation(2.0).x - x).abs() <= POSITION_GRID);
+    }
+}
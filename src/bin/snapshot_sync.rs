@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use demo_bevy::protocol::decode;
+use demo_bevy::movement::{Animation, JumpState};
+use demo_bevy::snapshot::{EntitySnapshot, Pose, SnapshotHistory, WorldSnapshot, WorldState};
+use demo_bevy::{ClientChannel, Player, PlayerInput, ServerChannel};
+use crate::{pickups::Pickup, Box, FakeBox, PlayerState, SimulationTick};
+use crate::violations::ProtocolViolation;
+use crate::rooms::RoomServer;
+
+const STATS_INTERVAL_SECONDS: f32 = 5.0;
+
+// world states sent recently and the newest one every client confirmed
+#[derive(Resource, Debug, Default)]
+pub struct SnapshotBaselines {
+    history: SnapshotHistory,
+    acks: HashMap<ClientId, u64>,
+}
+
+// bytes of snapshots sent, next to what the old full [f32; 3] encoding would have cost
+#[derive(Resource, Debug)]
+pub struct SnapshotStats {
+    pub enabled: bool,
+    ticks: u32,
+    bytes: usize,
+    full_bytes: usize,
+    timer: Timer,
+}
+
+impl SnapshotStats {
+    pub fn new(enabled: bool) -> Self {
+        SnapshotStats {
+            enabled,
+            ticks: 0,
+            bytes: 0,
+            full_bytes: 0,
+            timer: Timer::from_seconds(STATS_INTERVAL_SECONDS, TimerMode::Repeating),
+        }
+    }
+}
+
+// NetworkedEntities + NetworkedBoxes of one tick: tick, vec lengths, Entity, [f32; 3] and scale
+fn full_encoding_size(players: usize, boxes: usize) -> usize {
+    (8 + 3 * 8 + players * (8 + 12 + 4)) + (8 + 2 * 8 + boxes * (8 + 12))
+}
+
+pub fn receive_snapshot_acks(
+    mut server: RoomServer,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut violations: EventWriter<ProtocolViolation>,
+) {
+    for client_id in server.clients_id() {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::SnapshotAck) {
+            match decode::<u64>(&message) {
+                Ok(tick) => {
+                    let ack = baselines.acks.entry(client_id).or_default();
+                    *ack = (*ack).max(tick);
+                }
+                Err(error) => {
+                    violations.send(ProtocolViolation { client_id, channel: "snapshot ack", error });
+                }
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+//posiela info o poziciach hracov a boxov, kazdemu iba zmeny od snapshotu ktory uz ma
+pub fn server_network_sync(
+    mut server: RoomServer,
+    tick: Res<SimulationTick>,
+    mut baselines: ResMut<SnapshotBaselines>,
+    mut stats: ResMut<SnapshotStats>,
+    players_query: Query<(Entity, &Transform, &PlayerInput, &JumpState, &PlayerState), With<Player>>,
+    boxes_query: Query<(Entity, &Transform), Or<(With<Box>, With<FakeBox>, With<Pickup>)>>,
+) {
+    let mut state = WorldState::new();
+    for (entity, transform, input, jump_state, player_state) in players_query.iter() {
+        // animation changes go out with the position, a delta only has them when they change
+        let animation = Animation::of(input, jump_state, player_state.contacts(), player_state.dead);
+        let pose = Pose::new(animation, transform.scale.x < 0.0);
+        state.insert(entity.to_bits(), EntitySnapshot::new(entity, transform.translation, pose));
+    }
+    for (entity, transform) in boxes_query.iter() {
+        state.insert(entity.to_bits(), EntitySnapshot::new(entity, transform.translation, Pose::default()));
+    }
+
+    let clients = server.clients_id();
+    baselines.acks.retain(|client_id, _| clients.contains(client_id));
+    for client_id in clients.iter() {
+        let base = baselines.acks.get(client_id)
+            .and_then(|ack| baselines.history.get(*ack).map(|base| (*ack, base)));
+        let message = bincode::serialize(&WorldSnapshot::encode(tick.0, &state, base)).unwrap();
+        stats.bytes += message.len();
+        server.send_message(*client_id, ServerChannel::NetworkedEntities, message);
+    }
+    stats.ticks += 1;
+    stats.full_bytes += clients.len() * full_encoding_size(players_query.iter().len(), boxes_query.iter().len());
+    baselines.history.push(tick.0, state);
+}
+
+pub fn report_snapshot_stats(time: Res<Time>, mut stats: ResMut<SnapshotStats>) {
+    if !stats.enabled || !stats.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    if stats.ticks > 0 {
+        println!(
+            "Snapshots: {} bytes/tick, full encoding would be {} bytes/tick",
+            stats.bytes / stats.ticks as usize,
+            stats.full_bytes / stats.ticks as usize,
+        );
+    }
+    stats.ticks = 0;
+    stats.bytes = 0;
+    stats.full_bytes = 0;
+}
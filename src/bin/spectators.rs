@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_renet::renet::ClientId;
+use demo_bevy::level::Level;
+use demo_bevy::protocol::{clean_text, decode, MAX_CHAT_LEN, MAX_NAME_LEN};
+use demo_bevy::{ClientChannel, ClientCommand, ServerChannel, ServerMessages};
+use crate::violations::ProtocolViolation;
+use crate::{reconnect, score::Scores, spawn_player, ServerLobby};
+use crate::lives::StartingLives;
+use crate::rooms::RoomServer;
+
+#[derive(Debug)]
+pub struct Spectator {
+    pub name: String,
+    pub player_key: u64,
+}
+
+// clients without a player, they get snapshots and events like everybody else
+#[derive(Resource, Debug, Default)]
+pub struct Spectators {
+    pub clients: HashMap<ClientId, Spectator>,
+    queue: Vec<ClientId>, // want to play, first come first served
+}
+
+impl Spectators {
+    pub fn add(&mut self, client_id: ClientId, name: String, player_key: u64) {
+        self.clients.insert(client_id, Spectator { name, player_key });
+    }
+
+    pub fn remove(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+        self.queue.retain(|id| *id != client_id);
+    }
+
+    fn join_queue(&mut self, client_id: ClientId) {
+        if !self.queue.contains(&client_id) {
+            self.queue.push(client_id);
+        }
+    }
+
+    fn next_queued(&mut self) -> Option<(ClientId, Spectator)> {
+        if self.queue.is_empty() {
+            return None;
+        }
+        let client_id = self.queue.remove(0);
+        self.clients.remove(&client_id).map(|spectator| (client_id, spectator))
+    }
+}
+
+// players a match can have, the rest of the clients watch
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct MaxPlayers(pub usize);
+
+// spectators can chat and queue up, the rest of the commands needs a player
+pub fn receive_spectator_commands(
+    mut server: RoomServer,
+    mut spectators: ResMut<Spectators>,
+    mut violations: EventWriter<ProtocolViolation>,
+) {
+    let clients: Vec<ClientId> = spectators.clients.keys().copied().collect();
+    for client_id in clients {
+        while let Some(message) = server.receive_message(client_id, ClientChannel::Command) {
+            let command = match decode::<ClientCommand>(&message) {
+                Ok(command) => command,
+                Err(error) => {
+                    violations.send(ProtocolViolation { client_id, channel: "command", error });
+                    continue;
+                }
+            };
+            let Some(spectator) = spectators.clients.get_mut(&client_id) else {
+                break;
+            };
+            match command {
+                ClientCommand::JoinNextRound => {
+                    spectators.join_queue(client_id);
+                    let message = bincode::serialize(&ServerMessages::Spectating { queued: true }).unwrap();
+                    server.send_message(client_id, ServerChannel::ServerMessages, message);
+                }
+                ClientCommand::SetName { name } => {
+                    let name = clean_text(&name, MAX_NAME_LEN);
+                    if !name.is_empty() {
+                        spectator.name = name;
+                    }
+                }
+                ClientCommand::Chat { text } => {
+                    let text = clean_text(&text, MAX_CHAT_LEN);
+                    if text.is_empty() {
+                        continue;
+                    }
+                    println!("{} (spectator): {}", spectator.name, text);
+                    let message = ServerMessages::Chat { id: client_id, name: spectator.name.clone(), text };
+                    server.broadcast_message(ServerChannel::ServerMessages, bincode::serialize(&message).unwrap());
+                }
+                ClientCommand::Leave => {
+                    println!("Spectator {} left.", client_id);
+                    server.disconnect(client_id);
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+// runs while the server waits for a round, queued spectators take the free places
+#[allow(clippy::too_many_arguments)]
+pub fn promote_spectators(
+    mut commands: Commands,
+    mut server: RoomServer,
+    mut lobby: ResMut<ServerLobby>,
+    mut scores: ResMut<Scores>,
+    mut spectators: ResMut<Spectators>,
+    max_players: Res<MaxPlayers>,
+    away: Query<(), With<reconnect::Away>>,
+    level: Res<Level>,
+    lives: Res<StartingLives>,
+) {
+    // dropped players keep their place until they come back or expire
+    while lobby.players.len() + away.iter().count() < max_players.0 {
+        let Some((client_id, spectator)) = spectators.next_queued() else {
+            break;
+        };
+        println!("Spectator {} ({}) joins the next round.", client_id, spectator.name);
+        spawn_player(&mut commands, &mut server, &mut lobby, &mut scores, &level, lives.0, client_id, spectator.name, spectator.player_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rooms::RoomMembers;
+    use bevy_renet::renet::RenetServer;
+    use demo_bevy::connection_config;
+
+    #[test]
+    fn queued_spectators_fill_free_places_in_order() {
+        let mut app = App::new();
+        let mut spectators = Spectators::default();
+        for raw in 1..=3 {
+            spectators.add(ClientId::from_raw(raw), String::new(), raw);
+        }
+        spectators.join_queue(ClientId::from_raw(3));
+        spectators.join_queue(ClientId::from_raw(1));
+        spectators.join_queue(ClientId::from_raw(3));
+        app.insert_resource(RenetServer::new(connection_config()))
+            .init_resource::<RoomMembers>()
+            .init_resource::<ServerLobby>()
+            .init_resource::<Scores>()
+            .insert_resource(spectators)
+            .insert_resource(MaxPlayers(1))
+            .init_resource::<Level>()
+            .insert_resource(StartingLives(3))
+            .add_systems(Update, promote_spectators);
+
+        app.update();
+        let lobby = app.world.resource::<ServerLobby>();
+        assert_eq!(lobby.players.len(), 1);
+        assert!(lobby.players.contains_key(&ClientId::from_raw(3)));
+        let spectators = app.world.resource::<Spectators>();
+        assert_eq!(spectators.clients.len(), 2);
+        assert_eq!(spectators.queue, vec![ClientId::from_raw(1)]);
+    }
+}
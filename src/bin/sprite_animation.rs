@@ -1,10 +1,13 @@
-use bevy::prelude::{Component, Deref, DerefMut, Query, Res, TextureAtlas, Time, Timer, Transform};
-use bevy_renet::renet::ClientId;
+use bevy::prelude::{Color, Commands, Component, Deref, DerefMut, Entity, Query, Res, Sprite, TextureAtlas, Time, Timer, Visibility};
+use demo_bevy::movement::Animation;
+
+const JUMP_FRAME: usize = 3; // legs apart, there is no jump sheet
+const DEAD_COLOR: Color = Color::rgb(0.4, 0.4, 0.4);
+const BLINKS_PER_SECOND: f32 = 8.0;
+
 #[derive(Component)]
 pub struct Player{
-    pub id: ClientId,
-    pub should_animate: bool,
-    pub should_invert: bool
+    pub animation: Animation, // from snapshots, own player from the prediction
 }
 #[derive(Component)]
 pub(crate) struct AnimationIndices { // TO SAVE ANIMATION SLICES COUNT
@@ -13,33 +16,13 @@ pub(crate) struct AnimationIndices { // TO SAVE ANIMATION SLICES COUNT
 }
 #[derive(Component, Deref, DerefMut)]
 pub  struct AnimationTimer(pub Timer);
-pub fn  turn_on_animate(
-    id: ClientId,
-    query: &mut Query<(&mut Transform,&AnimationIndices, &mut AnimationTimer, &mut TextureAtlas, &mut Player)>
-){
-    for (_transform, _indices, _timer, _atlas,mut player) in query.iter_mut() {
-        if player.id == id{
-            player.should_animate = true;
-        }
-    }
-}
-pub fn  turn_off_animate(
-    id: ClientId,
-    query: &mut Query<(&mut Transform,&AnimationIndices, &mut AnimationTimer, &mut TextureAtlas, &mut Player)>
-){
-    for (_transform,_indices, _timer, _atlas,mut player) in query.iter_mut() {
-        if player.id == id{
-            player.should_animate = false;
-        }
-    }
-}
 pub fn  animate_sprite(
     time: Res<Time>,
-    mut query: Query<(&AnimationIndices, &mut AnimationTimer, &mut TextureAtlas, &Player)>,  // Query finds gets our Player
+    mut query: Query<(&AnimationIndices, &mut AnimationTimer, &mut TextureAtlas, &mut Sprite, &Player)>,  // Query finds gets our Player
 ) {
     // Animate sprite
-    for (indices, mut timer, mut atlas, player) in &mut query {
-        if player.should_animate{
+    for (indices, mut timer, mut atlas, mut sprite, player) in &mut query {
+        if player.animation == Animation::Running{
             timer.tick(time.delta());
             if timer.just_finished() {
                 atlas.index = if atlas.index == indices.last {
@@ -49,12 +32,30 @@ pub fn  animate_sprite(
                 };
             }
         } else {
-            // If no movement buttons are pressed, reset the animation
+            // If not running, reset the animation
             timer.reset();
-            atlas.index = indices.first;
+            atlas.index = if player.animation == Animation::Jumping { JUMP_FRAME.min(indices.last) } else { indices.first };
+        }
+        let color = if player.animation == Animation::Dead { DEAD_COLOR } else { Color::WHITE };
+        if sprite.color != color {
+            sprite.color = color;
         }
     }
 }
 
-#[allow(dead_code)]
-fn main() {}
\ No newline at end of file
+// respawned player boxes can not hurt yet
+#[derive(Component, Deref, DerefMut)]
+pub struct Blink(pub Timer);
+pub fn blink(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Blink, &mut Visibility)>,
+) {
+    for (entity, mut timer, mut visibility) in &mut query {
+        let visible = timer.tick(time.delta()).finished() || ((timer.elapsed_secs() * BLINKS_PER_SECOND) as u32).is_multiple_of(2);
+        *visibility = if visible { Visibility::Inherited } else { Visibility::Hidden };
+        if timer.finished() {
+            commands.entity(entity).remove::<Blink>();
+        }
+    }
+}
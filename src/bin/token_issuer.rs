@@ -0,0 +1,172 @@
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+use bevy_renet::renet::transport::{ConnectToken, NETCODE_KEY_BYTES};
+use demo_bevy::auth::{name_to_user_data, percent_decode, TOKEN_PATH};
+use demo_bevy::settings::ServerSettings;
+
+// how long a client has to use the token and how long the connection can stay silent
+const TOKEN_EXPIRE_SECONDS: u64 = 300;
+const TIMEOUT_SECONDS: i32 = 15;
+// a request has to arrive whole within this, however slowly its bytes trickle in
+const REQUEST_DEADLINE: Duration = Duration::from_millis(500);
+// request line and headers of a token request are tiny, anything longer is cut off
+const MAX_REQUEST_BYTES: u64 = 4096;
+// requests answered at the same time, connections over it are dropped right away
+const MAX_HANDLERS: usize = 16;
+
+// stand-in for a real matchmaking backend, hands out connect tokens over plain http on its own thread
+pub fn spawn_token_issuer(settings: &ServerSettings) -> SocketAddr {
+    let listener = TcpListener::bind(settings.token_addr())
+        .unwrap_or_else(|e| panic!("could not start token issuer on {}: {}", settings.token_addr(), e));
+    let issuer = Arc::new(TokenIssuer {
+        protocol_id: settings.protocol_id,
+        server_addresses: settings.public_addrs(),
+        private_key: settings.private_key(),
+        next_client_id: AtomicU64::new(
+            SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u64,
+        ),
+        handlers: AtomicUsize::new(0),
+    });
+    let local_addr = listener.local_addr().unwrap();
+    println!("Token issuer listening on {}", local_addr);
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if issuer.handlers.fetch_add(1, Ordering::SeqCst) >= MAX_HANDLERS {
+                issuer.handlers.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+            let issuer = issuer.clone();
+            thread::spawn(move || {
+                if let Err(e) = issuer.handle(stream) {
+                    println!("Token request failed: {}", e);
+                }
+                issuer.handlers.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    local_addr
+}
+
+struct TokenIssuer {
+    protocol_id: u64,
+    server_addresses: Vec<SocketAddr>,
+    private_key: [u8; NETCODE_KEY_BYTES],
+    next_client_id: AtomicU64,
+    handlers: AtomicUsize, // requests being answered right now
+}
+
+// reads from the stream until the deadline, the read timeout alone restarts with every byte
+struct DeadlineReader<'a> {
+    stream: &'a TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "token request took too long"));
+        }
+        let mut stream = self.stream;
+        stream.set_read_timeout(Some(left))?;
+        stream.read(buf)
+    }
+}
+
+impl TokenIssuer {
+    fn handle(&self, mut stream: TcpStream) -> io::Result<()> {
+        let deadline = Instant::now() + REQUEST_DEADLINE;
+        let mut reader = BufReader::new(DeadlineReader { stream: &stream, deadline }.take(MAX_REQUEST_BYTES));
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // read the rest of the headers so closing the socket does not reset the connection
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 2 {
+            header.clear();
+        }
+
+        // GET /token?name=... HTTP/1.1
+        let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        if !request_line.starts_with("GET ") || path != TOKEN_PATH {
+            return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        }
+        let name = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == "name")
+            .map(|(_, value)| percent_decode(value))
+            .unwrap_or_default();
+
+        let client_id = self.next_client_id.fetch_add(1, Ordering::Relaxed);
+        let current_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        let token = ConnectToken::generate(
+            current_time,
+            self.protocol_id,
+            TOKEN_EXPIRE_SECONDS,
+            client_id,
+            TIMEOUT_SECONDS,
+            self.server_addresses.clone(),
+            Some(&name_to_user_data(&name)),
+            &self.private_key,
+        ).map_err(|e| io::Error::other(e.to_string()))?;
+
+        let mut body = Vec::new();
+        token.write(&mut body)?;
+        println!("Issued connect token for {} ({})", name, client_id);
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )?;
+        stream.write_all(&body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use demo_bevy::auth::request_connect_token;
+
+    #[test]
+    fn issues_token_for_public_addresses() {
+        let mut settings = ServerSettings { token_port: 0, ..Default::default() };
+        settings.generate_missing_private_key();
+        let issuer = spawn_token_issuer(&settings);
+
+        // a silent connection does not hold up the others
+        let _silent = TcpStream::connect(issuer).unwrap();
+        let token = request_connect_token(issuer, "Jakub").unwrap();
+        assert_eq!(token.protocol_id, settings.protocol_id);
+        assert_eq!(token.server_addresses[0], Some(settings.bind_addr()));
+    }
+
+    #[test]
+    fn slow_clients_are_cut_off_and_busy_issuer_drops_the_rest() {
+        let mut settings = ServerSettings { token_port: 0, ..Default::default() };
+        settings.generate_missing_private_key();
+        let issuer = spawn_token_issuer(&settings);
+
+        // sending a byte now and then does not keep the connection open past the deadline
+        let mut slow = TcpStream::connect(issuer).unwrap();
+        slow.write_all(b"GET /to").unwrap();
+        thread::sleep(REQUEST_DEADLINE / 2);
+        slow.write_all(b"ken").unwrap();
+        slow.set_read_timeout(Some(REQUEST_DEADLINE * 4)).unwrap();
+        let started = Instant::now();
+        assert_eq!(slow.read(&mut [0; 16]).unwrap_or(0), 0);
+        assert!(started.elapsed() < REQUEST_DEADLINE * 2);
+
+        let silent: Vec<TcpStream> = (0..MAX_HANDLERS).map(|_| TcpStream::connect(issuer).unwrap()).collect();
+        assert!(request_connect_token(issuer, "Jakub").is_err());
+        thread::sleep(REQUEST_DEADLINE * 2);
+        assert!(request_connect_token(issuer, "Jakub").is_ok());
+        drop(silent);
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use bevy::prelude::*;
+use bevy_renet::renet::{ClientId, RenetServer};
+use demo_bevy::protocol::DecodeError;
+use crate::handshake::Handshakes;
+
+// a few bad messages can be a bug, more than that is somebody poking at the server
+const MAX_VIOLATIONS: u32 = 3;
+
+// client sent something the server could not decode
+#[derive(Event, Debug)]
+pub struct ProtocolViolation {
+    pub client_id: ClientId,
+    pub channel: &'static str,
+    pub error: DecodeError,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct Violations {
+    counts: HashMap<ClientId, u32>,
+}
+
+pub fn punish_violations(
+    mut server: ResMut<RenetServer>,
+    mut violations: ResMut<Violations>,
+    mut handshakes: ResMut<Handshakes>,
+    mut events: EventReader<ProtocolViolation>,
+) {
+    violations.counts.retain(|client_id, _| server.is_connected(*client_id));
+    for ProtocolViolation { client_id, channel, error } in events.read() {
+        let count = violations.counts.entry(*client_id).or_default();
+        *count += 1;
+        println!("Player {} sent a bad {} message ({}/{}): {}", client_id, channel, count, MAX_VIOLATIONS, error);
+        if *count == MAX_VIOLATIONS {
+            handshakes.kick(&mut server, *client_id, "Kicked for sending malformed data.".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use demo_bevy::connection_config;
+    use demo_bevy::protocol::decode;
+
+    #[test]
+    fn kicks_after_too_many_bad_messages() {
+        let mut app = App::new();
+        let mut server = RenetServer::new(connection_config());
+        let client_id = ClientId::from_raw(3);
+        server.add_connection(client_id);
+        app.init_resource::<Time>()
+            .init_resource::<Handshakes>()
+            .init_resource::<Violations>()
+            .add_event::<ProtocolViolation>()
+            .insert_resource(server)
+            .add_event::<crate::handshake::ClientWelcomed>()
+            .add_systems(Update, (punish_violations, crate::handshake::receive_hello).chain());
+
+        for i in 1..=MAX_VIOLATIONS {
+            let error = decode::<u64>(&[1, 2]).unwrap_err();
+            app.world.send_event(ProtocolViolation { client_id, channel: "input", error });
+            app.update();
+            assert_eq!(app.world.resource::<Violations>().counts[&client_id], i);
+        }
+        // kicked once the rejection had time to go out
+        assert!(app.world.resource::<RenetServer>().is_connected(client_id));
+        app.world.resource_mut::<Time>().advance_by(std::time::Duration::from_secs(2));
+        app.update();
+        assert!(!app.world.resource::<RenetServer>().is_connected(client_id));
+    }
+}
@@ -0,0 +1,169 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const LEVELS_DIR: &str = "assets/levels"; // under levels_root()
+pub const DEFAULT_LEVEL: &str = "warehouse";
+const MAX_LEVEL_ID_LEN: usize = 32;
+const COLUMN_GAP_ERROR: f32 = 0.01;
+
+// where the players can walk, movement and the server collisions keep them inside
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Arena {
+    pub floor: f32,
+    pub left_wall: f32,
+    pub right_wall: f32,
+}
+
+// rail the crane drives the boxes on, they come in from left or right and drop at drop_height
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Crane {
+    pub height: f32,
+    pub left: f32,
+    pub right: f32,
+    pub drop_height: f32,
+}
+
+// picture behind the game, path is inside assets/
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Art {
+    pub image: String,
+    pub position: (f32, f32, f32),
+    pub scale: f32,
+}
+
+// one arena, read from assets/levels/<id>.ron by the server and the client
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Level {
+    #[serde(skip)]
+    pub id: String, // file name, sent to the clients
+    pub arena: Arena,
+    pub columns: Vec<f32>, // sorted left to right
+    pub crane: Crane,
+    pub player_spawns: Vec<(f32, f32)>,
+    pub art: Vec<Art>,
+}
+
+// same lookup bevy uses for the asset folder, so the levels are found from any working directory
+pub fn levels_root() -> PathBuf {
+    if let Some(root) = env::var_os("BEVY_ASSET_ROOT").or_else(|| env::var_os("CARGO_MANIFEST_DIR")) {
+        return PathBuf::from(root);
+    }
+    env::current_exe().ok()
+        .and_then(|exe| exe.parent().map(PathBuf::from))
+        .unwrap_or_default()
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Level::parse(DEFAULT_LEVEL, include_str!("../assets/levels/warehouse.ron")).unwrap()
+    }
+}
+
+impl Level {
+    pub fn load(id: &str) -> Result<Level, String> {
+        // the id comes from the server, it must not point outside the levels directory
+        if id.is_empty() || id.len() > MAX_LEVEL_ID_LEN || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("bad level id {:?}", id));
+        }
+        let path = levels_root().join(LEVELS_DIR).join(format!("{}.ron", id));
+        let text = fs::read_to_string(&path).map_err(|e| format!("can not read {}: {}", path.display(), e))?;
+        Level::parse(id, &text)
+    }
+
+    pub fn parse(id: &str, text: &str) -> Result<Level, String> {
+        let mut level: Level = ron::from_str(text).map_err(|e| format!("level {}: {}", id, e))?;
+        level.id = id.to_string();
+        level.columns.sort_by(f32::total_cmp);
+        if level.arena.left_wall >= level.arena.right_wall {
+            return Err(format!("level {}: left wall is not left of the right wall", id));
+        }
+        if level.columns.len() < 2 || level.player_spawns.is_empty() {
+            return Err(format!("level {}: needs at least two columns and one player spawn", id));
+        }
+        // boxes stack on a grid, column_tolerance and the crane assume the same gap everywhere
+        let gap = level.columns[1] - level.columns[0];
+        if !gap.is_finite() || gap <= 0.0 || level.columns.windows(2).any(|pair| (pair[1] - pair[0] - gap).abs() > COLUMN_GAP_ERROR) {
+            return Err(format!("level {}: columns have to be apart and evenly spaced", id));
+        }
+        if level.crane.left >= level.crane.right {
+            return Err(format!("level {}: crane rail has no length", id));
+        }
+        Ok(level)
+    }
+
+    // players take the spawns in order of joining, more players than spawns share them
+    pub fn player_spawn(&self, index: usize) -> Vec3 {
+        let (x, y) = self.player_spawns[index % self.player_spawns.len()];
+        Vec3::new(x, y, 2.0)
+    }
+
+    pub fn leftmost_column(&self) -> f32 {
+        self.columns[0]
+    }
+
+    pub fn rightmost_column(&self) -> f32 {
+        self.columns[self.columns.len() - 1]
+    }
+
+    // half the distance between two columns, a box closer than this to a column is in it
+    pub fn column_tolerance(&self) -> f32 {
+        (self.columns[1] - self.columns[0]) / 2.0
+    }
+}
+
+// sprites spawned from Level::art, replaced when the server picks another level
+#[derive(Component)]
+pub struct LevelArt;
+
+pub fn spawn_level_art(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    level: Res<Level>,
+    art: Query<Entity, With<LevelArt>>,
+) {
+    for entity in art.iter() {
+        commands.entity(entity).despawn();
+    }
+    for art in &level.art {
+        let (x, y, z) = art.position;
+        commands.spawn((SpriteBundle {
+            transform: Transform::from_xyz(x, y, z) * Transform::from_scale(Vec3::splat(art.scale)),
+            texture: asset_server.load(art.image.clone()),
+            ..default()
+        }, LevelArt));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_level_loads_and_bad_ids_do_not() {
+        let level = Level::default();
+        assert_eq!(level.id, DEFAULT_LEVEL);
+        assert_eq!(level.columns.len(), 14);
+        assert_eq!(level.column_tolerance(), 35.0);
+        assert_eq!(level.player_spawn(level.player_spawns.len()), level.player_spawn(0));
+        assert_eq!(Level::load(DEFAULT_LEVEL).unwrap(), level);
+        assert!(levels_root().join(LEVELS_DIR).is_absolute());
+        assert!(Level::load("../Cargo").is_err());
+        assert!(Level::load("missing").is_err());
+        assert!(Level::parse("bad", "(arena: (floor: 0.0, left_wall: 1.0, right_wall: 0.0), columns: [0.0, 70.0], \
+            crane: (height: 0.0, left: 0.0, right: 1.0, drop_height: 0.0), player_spawns: [(0.0, 0.0)], art: [])").is_err());
+    }
+
+    #[test]
+    fn columns_have_to_be_apart_and_evenly_spaced() {
+        let with_columns = |columns: &str| Level::parse("test", &format!("(arena: (floor: 0.0, left_wall: 0.0, right_wall: 300.0), columns: {}, \
+            crane: (height: 0.0, left: 0.0, right: 1.0, drop_height: 0.0), player_spawns: [(0.0, 0.0)], art: [])", columns));
+        assert_eq!(with_columns("[140.0, 0.0, 70.0]").unwrap().column_tolerance(), 35.0);
+        assert!(with_columns("[0.0, 70.0, 70.0]").is_err());
+        assert!(with_columns("[0.0, 0.0]").is_err());
+        assert!(with_columns("[0.0, 20.0, 140.0]").is_err());
+    }
+}
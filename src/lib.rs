@@ -4,49 +4,173 @@ use bevy::prelude::*;
 use bevy_renet::renet::{ChannelConfig, ClientId, ConnectionConfig, SendType};
 use serde::{Deserialize, Serialize};
 
-#[cfg(feature = "transport")]
-pub const PRIVATE_KEY: &[u8; bevy_renet::renet::transport::NETCODE_KEY_BYTES] = b"an example very very secret key."; // 32-bytes
 #[cfg(feature = "transport")]
 pub const PROTOCOL_ID: u64 = 7;
 
+// how many simulation ticks the server runs per second
+pub const DEFAULT_TICK_RATE: f64 = 60.0;
+
+#[cfg(feature = "transport")]
+pub mod auth;
+#[cfg(feature = "transport")]
+pub mod settings;
+pub mod movement;
+pub mod level;
+pub mod snapshot;
+pub mod protocol;
+
 #[derive(Debug, Component)]
 pub struct Player {
     pub id: ClientId,
     pub asset: String, // keep track of players asset to draw correct for every player
+    pub name: String, // from user_data of the connect token
 }
 #[derive(Component)]
 pub struct ReadyText;
 
+// phase of the match on the server, sent to clients that join in the middle of it
+#[derive(States, Debug, Default, Hash, Clone, Eq, PartialEq, Copy, Serialize, Deserialize)]
+pub enum RunState {
+    #[default]
+    Waiting,
+    Playing,
+    GameOver,
+}
+
+// what a box does besides falling, the server rolls it when the crane picks the box up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component)]
+pub enum BoxKind {
+    #[default]
+    Plain,
+    Heavy, // moves only when two players push it the same way
+    Bomb, // clears the boxes around it a while after it lands
+    Ice, // slides after a push until it hits something
+    Fragile, // breaks when a box or a player lands on it
+}
+
+impl BoxKind {
+    pub fn sprite(&self) -> &'static str {
+        match self {
+            BoxKind::Plain => "box2.png",
+            BoxKind::Heavy => "box_heavy.png",
+            BoxKind::Bomb => "box_bomb.png",
+            BoxKind::Ice => "box_ice.png",
+            BoxKind::Fragile => "box_fragile.png",
+        }
+    }
+}
+
+// bonuses the crane drops instead of a box now and then, a player collects them by walking into them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Component)]
+pub enum PickupKind {
+    ExtraLife, // survives one more box
+    DoubleJump, // jump again in the air
+    SpeedBoost,
+    Shield, // a box falling on the player breaks instead
+    LineBomb, // clears the lowest row right away
+}
+
+impl PickupKind {
+    pub fn sprite(&self) -> &'static str {
+        match self {
+            PickupKind::ExtraLife => "pickup_life.png",
+            PickupKind::DoubleJump => "pickup_double_jump.png",
+            PickupKind::SpeedBoost => "pickup_speed.png",
+            PickupKind::Shield => "pickup_shield.png",
+            PickupKind::LineBomb => "pickup_line_bomb.png",
+        }
+    }
+}
+
+// player sprite sheets, ClientCommand::PickSkin is an index into this
+pub const SKINS: [&str; 3] = ["running_animation.png", "running_animation2.png", "running_animation3.png"];
+
 #[derive(Debug, Default, Clone, Copy, Serialize, Deserialize, Component, Resource)]
 pub struct PlayerInput {
     pub up: bool,
     pub down: bool,
     pub left: bool,
     pub right: bool,
-    pub ready: bool,
+}
+
+// input of one client tick, numbered so the server can tell the client which one it simulated last
+#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+pub struct SequencedInput {
+    pub sequence: u64,
+    pub input: PlayerInput,
+}
+
+// inputs in one bundle, a packet can get lost this many times in a row before the server misses an input
+pub const INPUT_REDUNDANCY: usize = 4;
+
+// sent every tick on ClientChannel::Input, the newest inputs the server has not acked yet, oldest first
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InputBundle {
+    pub inputs: Vec<SequencedInput>,
 }
 
 pub enum ClientChannel {
     Input,
     Command,
+    SnapshotAck,
 }
 pub enum ServerChannel {
     ServerMessages,
     NetworkedEntities,
-    NetworkedBoxes,
+    InputAck,
+    Handshake,
+}
+
+// how fast the match gets harder, picked in the waiting room
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
 }
 
 #[derive(Debug, Default, Component)]
 pub struct Velocity(pub Vec3);
 
+// sent by the client on ClientChannel::Command
+#[derive(Debug, Serialize, Deserialize)]
+pub enum ClientCommand {
+    // first message after connecting, nothing else is sent before the server welcomes us
+    Hello {
+        protocol_version: u32,
+        build_hash: u64,
+        // random for every start of the game, connecting again with it takes back the same player
+        player_key: u64,
+        // code of the room to play in, a room is made for a code nobody uses yet, empty gets a new code
+        room: String,
+    },
+    // only while waiting for the match to start
+    Ready,
+    Unready,
+    SetName { name: String },
+    PickSkin { skin: u8 },
+    PickDifficulty { difficulty: Difficulty },
+    Chat { text: String },
+    // restarts the round once most of the players voted
+    VoteRestart,
+    // spectators only, take the next free place when the server waits for a round
+    JoinNextRound,
+    // server disconnects us right away instead of waiting for the timeout
+    Leave,
+}
+
 
 #[derive(Debug, Serialize, Deserialize, Component)]
 pub enum ServerMessages {
+    // sent once the server put us in a room, others join with the same code, level is a file in assets/levels
+    Room{code: String, level: String},
     PlayerCreate {
         entity: Entity,
         id: ClientId,
         translation: [f32; 3],
         asset: String, // to assign asset for new player connected
+        name: String,
     },
     PlayerRemove {
         id: ClientId,
@@ -60,6 +184,13 @@ pub enum ServerMessages {
     SpawnBox {
         entity: Entity,
         translation: [f32; 3],
+        kind: BoxKind,
+    },
+    // on the crane and lying on the field, removed with DespawnBox like boxes
+    SpawnPickup {
+        entity: Entity,
+        translation: [f32; 3],
+        kind: PickupKind,
     },
     DespawnBox {
         entity_to_despawn: Entity,
@@ -67,29 +198,52 @@ pub enum ServerMessages {
     SoundAction{
         sound: u8,
     },
-    AnimatePlayer{
-        id: ClientId,
-        facing_right: bool
-    },
-    StopAnimate{
-        id: ClientId,
-    },
-    ExitWindow{},
     YouReady{id: ClientId},
+    NotReady{id: ClientId},
     YouDead{id: ClientId},
+    // last message of the world sent to a client that just joined
+    JoinSync{state: RunState},
+    PlayerRenamed{id: ClientId, name: String},
+    PlayerSkin{id: ClientId, asset: String},
+    // for the whole room, also sent to clients that join
+    Difficulty{difficulty: Difficulty},
+    // spectators have no player on the client, so the name comes with the text
+    Chat{id: ClientId, name: String, text: String},
+    RestartVotes{votes: u32, needed: u32},
+    // boxes are gone, players are back at the start and wait for ready again
+    RoundRestart{},
+    // everybody died, final scores of the round for the results screen
+    RoundOver{
+        team: u32,
+        players: Vec<(ClientId, u32)>,
+    },
+    // we have no player, only watch the match until a place for us frees up
+    Spectating{queued: bool},
+    // our own power-ups after one was collected, used or ran out, seconds left for the timed ones
+    PowerUps{
+        timed: Vec<(PickupKind, f32)>,
+    },
+    // lives left of a player, with some left it comes back after a while, see Invulnerable
+    Lives{id: ClientId, lives: u32},
+    // player just respawned and boxes can not hurt it for a moment, clients make it blink
+    Invulnerable{id: ClientId, seconds: f32},
+    // score of the whole team and of every connected player
+    ScoreUpdate{
+        team: u32,
+        players: Vec<(ClientId, u32)>,
+    },
 }
 
+// sent only to the owner of the player, client replays its newer inputs on top of this
 #[derive(Debug, Serialize, Deserialize, Default)]
-pub struct NetworkedEntities {
-    // tu potom pridaj entity type a tak bude vediet client ako scalovat obrazok podla toho type
-    pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
-    pub scales: Vec<f32>,
-}
-#[derive(Debug, Serialize, Deserialize, Default)]
-pub struct NetworkedBoxes{
-    pub entities: Vec<Entity>,
-    pub translations: Vec<[f32; 3]>,
+pub struct InputAck {
+    pub tick: u64,
+    pub sequence: u64, // last input simulated, 0 when none yet
+    pub translation: [f32; 3],
+    pub scale: f32,
+    pub jump: movement::JumpSnapshot,
+    pub contacts: movement::Contacts,
+    pub boosts: movement::Boosts,
 }
 
 impl From<ClientChannel> for u8 {
@@ -97,6 +251,7 @@ impl From<ClientChannel> for u8 {
         match channel_id {
             ClientChannel::Command => 0,
             ClientChannel::Input => 1,
+            ClientChannel::SnapshotAck => 2,
 
         }
     }
@@ -105,12 +260,11 @@ impl From<ClientChannel> for u8 {
 impl ClientChannel {
     pub fn channels_config() -> Vec<ChannelConfig> {
         vec![
+            // InputBundle every tick, lost packets are covered by the next bundle instead of resends
             ChannelConfig {
                 channel_id: Self::Input.into(),
                 max_memory_usage_bytes: 5 * 1024 * 1024,
-                send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::ZERO,
-                },
+                send_type: SendType::Unreliable,
             },
             ChannelConfig {
                 channel_id: Self::Command.into(),
@@ -119,6 +273,12 @@ impl ClientChannel {
                     resend_time: Duration::ZERO,
                 },
             },
+            // tick of the newest snapshot the client has, server sends deltas against it
+            ChannelConfig {
+                channel_id: Self::SnapshotAck.into(),
+                max_memory_usage_bytes: 5 * 1024 * 1024,
+                send_type: SendType::Unreliable,
+            },
         ]
     }
 }
@@ -128,7 +288,8 @@ impl From<ServerChannel> for u8 {
         match channel_id {
             ServerChannel::NetworkedEntities => 0,
             ServerChannel::ServerMessages => 1,
-            ServerChannel::NetworkedBoxes => 2,
+            ServerChannel::InputAck => 2,
+            ServerChannel::Handshake => 3,
         }
     }
 }
@@ -136,18 +297,26 @@ impl From<ServerChannel> for u8 {
 impl ServerChannel {
     pub fn channels_config() -> Vec<ChannelConfig> {
         vec![
-            // na sync pozicii hracov
+            // na sync pozicii hracov a boxov, snapshot::WorldSnapshot
             ChannelConfig {
                 channel_id: Self::NetworkedEntities.into(),
                 max_memory_usage_bytes: 10 * 1024 * 1024,
                 send_type: SendType::Unreliable,
             },
-            // na sync pozicii boxov
+            // potvrdenie inputu pre predikciu
             ChannelConfig {
-                channel_id: Self::NetworkedBoxes.into(),
+                channel_id: Self::InputAck.into(),
                 max_memory_usage_bytes: 10 * 1024 * 1024,
                 send_type: SendType::Unreliable,
             },
+            // protocol::HandshakeReply, odpoved na ClientCommand::Hello
+            ChannelConfig {
+                channel_id: Self::Handshake.into(),
+                max_memory_usage_bytes: 1024 * 1024,
+                send_type: SendType::ReliableOrdered {
+                    resend_time: Duration::from_millis(200),
+                },
+            },
             // server messages su napriklad spawni hraca spawni boxu je to enum
             ChannelConfig {
                 channel_id: Self::ServerMessages.into(),
@@ -168,26 +337,12 @@ pub fn connection_config() -> ConnectionConfig {
     }
 }
 
-/// set up a simple 3D scene
-pub fn setup_level(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>, // Adds /assets dir
-) {
-    // SPAWN BACKGROUND
-    commands.spawn(SpriteBundle{
-        transform: Transform::from_translation(Vec3::new(0.0, 200.0, 0.0)),
-        texture: asset_server.load("origbig.png"),
-        ..default()
-    });
-    let texture_map = asset_server.load("map_version1.png");
-    commands.spawn(
-        SpriteBundle{
-            transform: Transform::from_translation(Vec3::new(100.0, 0.0, 0.0)) // transform move sprite
-                * Transform::from_scale(Vec3::splat(4.0)), // scale sprite
-            texture: texture_map,
-            ..default()
-        }
-    );
+/// set up a simple 3D scene, the art comes from the level (see level::spawn_level_art)
+pub fn setup_level(mut commands: Commands) {
+    spawn_ready_text(&mut commands);
+}
+
+pub fn spawn_ready_text(commands: &mut Commands) {
     commands.spawn((TextBundle::from_sections([TextSection::new(
         "PRESS -R- TO GET READY",
         TextStyle {
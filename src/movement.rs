@@ -0,0 +1,301 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::level::Arena;
+use crate::PlayerInput;
+
+// movement rules shared by the server simulation and the client prediction
+pub const PLAYER_SPEED: f32 = 400.0;
+pub const JUMP_VELOCITY: f32 = 500.0;
+const SPEED_BOOST: f32 = 1.5;
+const JUMP_UP_SECONDS: f32 = 0.2;
+const JUMP_SECONDS: f32 = 0.4;
+
+// sides where the player leans on a box or stands on something, filled by the server collisions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Contacts {
+    pub left: bool,
+    pub right: bool,
+    pub below: bool,
+}
+
+// power-ups that change movement, the server sends them with every ack so prediction moves the same way
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Boosts {
+    pub speed: bool,
+    pub double_jump: bool,
+}
+
+// player movement
+#[derive(Component, Debug, Clone)]
+pub struct JumpState {
+    pub is_jumping: bool,
+    pub jump_timer: Timer,
+    pub fall_timer: Timer,
+    pub can_jump: bool,
+    pub floor_reset: bool, // nech moze skocit iba ked sa dotkne zeme od posledneho skoku
+    pub air_jump: bool, // double jump not used since the last landing
+    pub up_held: bool, // the air jump needs a new press
+}
+
+impl Default for JumpState {
+    fn default() -> Self {
+        JumpState {
+            is_jumping: false,
+            floor_reset: true,
+            can_jump: true,
+            air_jump: true,
+            up_held: false,
+            jump_timer: Timer::from_seconds(JUMP_UP_SECONDS, TimerMode::Once),
+            fall_timer: Timer::from_seconds(JUMP_SECONDS, TimerMode::Once),
+        }
+    }
+}
+
+// JumpState without the timers so it can go over the network
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JumpSnapshot {
+    pub is_jumping: bool,
+    pub jump_elapsed: f32,
+    pub fall_elapsed: f32,
+    pub can_jump: bool,
+    pub floor_reset: bool,
+    pub air_jump: bool,
+    pub up_held: bool,
+}
+
+impl From<&JumpState> for JumpSnapshot {
+    fn from(jump_state: &JumpState) -> Self {
+        JumpSnapshot {
+            is_jumping: jump_state.is_jumping,
+            jump_elapsed: jump_state.jump_timer.elapsed_secs(),
+            fall_elapsed: jump_state.fall_timer.elapsed_secs(),
+            can_jump: jump_state.can_jump,
+            floor_reset: jump_state.floor_reset,
+            air_jump: jump_state.air_jump,
+            up_held: jump_state.up_held,
+        }
+    }
+}
+
+impl From<JumpSnapshot> for JumpState {
+    fn from(snapshot: JumpSnapshot) -> Self {
+        let mut jump_state = JumpState {
+            is_jumping: snapshot.is_jumping,
+            can_jump: snapshot.can_jump,
+            floor_reset: snapshot.floor_reset,
+            air_jump: snapshot.air_jump,
+            up_held: snapshot.up_held,
+            ..default()
+        };
+        // ticking instead of set_elapsed so finished() is right too
+        jump_state.jump_timer.tick(Duration::from_secs_f32(snapshot.jump_elapsed));
+        jump_state.fall_timer.tick(Duration::from_secs_f32(snapshot.fall_elapsed));
+        jump_state
+    }
+}
+
+// what the player sprite shows, the server sends it in snapshots and the client predicts its own
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Animation {
+    #[default]
+    Idle = 0,
+    Running = 1,
+    Jumping = 2,
+    Dead = 3,
+}
+
+impl Animation {
+    pub fn of(input: &PlayerInput, jump_state: &JumpState, contacts: Contacts, dead: bool) -> Self {
+        if dead {
+            Animation::Dead
+        } else if jump_state.is_jumping || !contacts.below {
+            Animation::Jumping
+        } else if input.left != input.right {
+            Animation::Running
+        } else {
+            Animation::Idle
+        }
+    }
+}
+
+// one tick of player movement, returns true when a jump started this tick
+pub fn move_player(
+    transform: &mut Transform,
+    input: &PlayerInput,
+    jump_state: &mut JumpState,
+    contacts: Contacts,
+    boosts: Boosts,
+    arena: &Arena,
+    delta: Duration,
+) -> bool {
+    let delta_seconds = delta.as_secs_f32();
+    let x = (input.right as i8 - input.left as i8) as f32;
+    let speed = if boosts.speed { PLAYER_SPEED * SPEED_BOOST } else { PLAYER_SPEED };
+
+    // To flip player
+    if x > 0.0{
+        transform.scale.x = 4.0;
+    }else if x < 0.0{
+        transform.scale.x = -4.0;
+    }
+    let mut new_player_position_x = (transform.translation.x + x * speed * delta_seconds)
+        .clamp(arena.left_wall, arena.right_wall);
+
+    if new_player_position_x > transform.translation.x && contacts.right{
+        new_player_position_x = transform.translation.x;
+    }
+    if new_player_position_x < transform.translation.x && contacts.left{
+        new_player_position_x = transform.translation.x;
+    }
+
+    // toto je nechutne prepojene s collision checkingom ale co uz
+    if contacts.below{  // is standing on something
+        jump_state.is_jumping = false;
+        jump_state.floor_reset = true;
+        jump_state.air_jump = true;
+    }
+    else if !jump_state.is_jumping{ // is not in the jump phase
+        transform.translation.y -= delta_seconds*PLAYER_SPEED;
+    }
+
+    transform.translation.x = new_player_position_x;
+    // teraz y suradnicu riesime:
+    let mut jumped = false;
+    if input.up && !jump_state.is_jumping &&  jump_state.can_jump && jump_state.floor_reset {
+        //START JUMP WHEN PRESSED checking jump_state so doesnt jump again when pressing
+        jumped = true;
+        jump_state.is_jumping = true;
+        jump_state.floor_reset = false;
+        jump_state.can_jump = false;
+        jump_state.jump_timer.reset();
+        jump_state.fall_timer.reset();
+    } else if input.up && !jump_state.up_held && boosts.double_jump && jump_state.air_jump && !contacts.below {
+        // second jump starts from wherever the player is in the air
+        jumped = true;
+        jump_state.is_jumping = true;
+        jump_state.air_jump = false;
+        jump_state.jump_timer.reset();
+        jump_state.fall_timer.reset();
+    }
+    jump_state.up_held = input.up;
+    if jump_state.is_jumping {
+        // Jumping up
+        if !jump_state.jump_timer.finished(){
+            transform.translation.y += JUMP_VELOCITY * delta_seconds;
+        }
+        //Jumping down when up is finished
+        if jump_state.jump_timer.finished() {
+            transform.translation.y -= JUMP_VELOCITY * delta_seconds;
+        }
+        //Tick timers and check finish
+        jump_state.jump_timer.tick(delta);
+        jump_state.fall_timer.tick(delta);
+        if jump_state.fall_timer.finished(){
+            jump_state.is_jumping = false;
+        }
+    }
+    if jump_state.floor_reset {
+        jump_state.can_jump = true;
+    }
+    jumped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::Level;
+
+    const TICK: Duration = Duration::from_nanos(16_666_667);
+
+    // stands the player back on the floor the way the server collisions do
+    fn land(transform: &mut Transform, arena: &Arena) -> Contacts {
+        let below = transform.translation.y <= arena.floor;
+        if below {
+            transform.translation.y = arena.floor;
+        }
+        Contacts { below, ..default() }
+    }
+
+    #[test]
+    fn held_jump_waits_for_landing() {
+        let arena = Level::default().arena;
+        let mut transform = Transform::from_xyz(0.0, arena.floor, 2.0);
+        let mut jump_state = JumpState::default();
+        let mut contacts = Contacts { below: true, ..default() };
+        let input = PlayerInput { up: true, ..default() };
+
+        let mut jumps = Vec::new();
+        let mut highest = arena.floor;
+        for tick in 0..40 {
+            if move_player(&mut transform, &input, &mut jump_state, contacts, Boosts::default(), &arena, TICK) {
+                jumps.push(tick);
+            }
+            contacts = land(&mut transform, &arena);
+            highest = highest.max(transform.translation.y);
+        }
+        // 0.4 s in the air, next jump only after touching the floor again
+        assert_eq!(jumps.len(), 2);
+        assert!(jumps[1] - jumps[0] >= 24);
+        assert!(highest > arena.floor + 90.0);
+    }
+
+    #[test]
+    fn walls_and_contacts_stop_walking() {
+        let arena = Level::default().arena;
+        let mut transform = Transform::from_xyz(arena.right_wall - 1.0, arena.floor, 2.0);
+        let mut jump_state = JumpState::default();
+        let contacts = Contacts { below: true, ..default() };
+        move_player(&mut transform, &PlayerInput { right: true, ..default() }, &mut jump_state, contacts, Boosts::default(), &arena, TICK);
+        assert_eq!(transform.translation.x, arena.right_wall);
+
+        let leaning = Contacts { left: true, below: true, ..default() };
+        move_player(&mut transform, &PlayerInput { left: true, ..default() }, &mut jump_state, leaning, Boosts::default(), &arena, TICK);
+        assert_eq!(transform.translation.x, arena.right_wall);
+        assert_eq!(transform.scale.x, -4.0);
+    }
+
+    #[test]
+    fn double_jump_needs_a_new_press_in_the_air() {
+        let arena = Level::default().arena;
+        let mut transform = Transform::from_xyz(0.0, arena.floor, 2.0);
+        let mut jump_state = JumpState::default();
+        let boosts = Boosts { double_jump: true, ..default() };
+        let up = PlayerInput { up: true, ..default() };
+        let mut contacts = Contacts { below: true, ..default() };
+        let mut jumps = 0;
+        // held up, then released and pressed again while falling
+        for tick in 0..60 {
+            let input = if (15..18).contains(&tick) { PlayerInput::default() } else { up };
+            if move_player(&mut transform, &input, &mut jump_state, contacts, boosts, &arena, TICK) {
+                jumps += 1;
+            }
+            contacts = land(&mut transform, &arena);
+            if tick == 20 {
+                assert!(!contacts.below);
+                assert!(!jump_state.air_jump);
+            }
+        }
+        assert!(jumps >= 2);
+        let walked = |boosts| {
+            let mut transform = Transform::from_xyz(0.0, arena.floor, 2.0);
+            let input = PlayerInput { right: true, ..default() };
+            move_player(&mut transform, &input, &mut JumpState::default(), Contacts { below: true, ..default() }, boosts, &arena, TICK);
+            transform.translation.x
+        };
+        assert!(walked(Boosts { speed: true, ..default() }) > walked(Boosts::default()));
+    }
+
+    #[test]
+    fn jump_snapshot_keeps_timers() {
+        let mut jump_state = JumpState { is_jumping: true, ..default() };
+        jump_state.jump_timer.tick(Duration::from_secs_f32(0.3));
+        jump_state.fall_timer.tick(Duration::from_secs_f32(0.3));
+        let restored = JumpState::from(JumpSnapshot::from(&jump_state));
+        assert!(restored.jump_timer.finished());
+        assert!(!restored.fall_timer.finished());
+        assert!(restored.is_jumping);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt;
+
+use bincode::Options;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+// nothing we send comes close, bigger messages are garbage or an attack
+pub const MAX_MESSAGE_BYTES: usize = 64 * 1024;
+
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_CHAT_LEN: usize = 120;
+pub const MAX_ROOM_CODE_LEN: usize = 8;
+
+// bump when messages change in a way the hash below does not see
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// hash of the files with the wire format and the shared movement rules,
+// client and server built from different versions of them can not play together
+pub const BUILD_HASH: u64 = {
+    let hash = fnv1a(FNV_OFFSET, include_str!("lib.rs").as_bytes());
+    let hash = fnv1a(hash, include_str!("movement.rs").as_bytes());
+    let hash = fnv1a(hash, include_str!("snapshot.rs").as_bytes());
+    fnv1a(hash, include_str!("protocol.rs").as_bytes())
+};
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// '\r' is skipped so a windows checkout hashes the same
+const fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\r' {
+            hash ^= bytes[i] as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        }
+        i += 1;
+    }
+    hash
+}
+
+// answer to ClientCommand::Hello on ServerChannel::Handshake,
+// old clients have to be able to read it so never change its layout
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HandshakeReply {
+    Welcome,
+    Rejected { reason: String },
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    TooLarge { len: usize },
+    Malformed(bincode::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooLarge { len } => write!(f, "message of {} bytes is over the {} byte limit", len, MAX_MESSAGE_BYTES),
+            DecodeError::Malformed(e) => write!(f, "malformed message: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// counterpart of bincode::serialize for everything that comes from the network,
+// same encoding but with a size limit and no leftover bytes allowed
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, DecodeError> {
+    if bytes.len() > MAX_MESSAGE_BYTES {
+        return Err(DecodeError::TooLarge { len: bytes.len() });
+    }
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(MAX_MESSAGE_BYTES as u64)
+        .deserialize(bytes)
+        .map_err(DecodeError::Malformed)
+}
+
+// names and chat are shown to other players so keep them short and printable
+pub fn clean_text(text: &str, max_len: usize) -> String {
+    let mut clean = String::new();
+    for c in text.trim().chars().filter(|c| !c.is_control()) {
+        if clean.len() + c.len_utf8() > max_len {
+            break;
+        }
+        clean.push(c);
+    }
+    clean
+}
+
+// reason for the client when its hello does not match this build
+pub fn check_hello(protocol_version: u32, build_hash: u64) -> Result<(), String> {
+    if protocol_version != PROTOCOL_VERSION {
+        return Err(format!(
+            "Server runs protocol version {}, your game has version {}. Update the game.",
+            PROTOCOL_VERSION, protocol_version
+        ));
+    }
+    if build_hash != BUILD_HASH {
+        return Err(format!(
+            "Server build {:016x} does not match your build {:016x}. Update the game.",
+            BUILD_HASH, build_hash
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hello_has_to_match_build() {
+        assert_eq!(check_hello(PROTOCOL_VERSION, BUILD_HASH), Ok(()));
+        assert!(check_hello(PROTOCOL_VERSION + 1, BUILD_HASH).unwrap_err().contains("protocol version"));
+        assert!(check_hello(PROTOCOL_VERSION, BUILD_HASH ^ 1).is_err());
+        assert_eq!(fnv1a(FNV_OFFSET, b"a\r\nb"), fnv1a(FNV_OFFSET, b"a\nb"));
+    }
+
+    #[test]
+    fn decode_matches_serialize_and_rejects_garbage() {
+        let reply = HandshakeReply::Rejected { reason: "old".to_string() };
+        let bytes = bincode::serialize(&reply).unwrap();
+        assert_eq!(decode::<HandshakeReply>(&bytes).unwrap(), reply);
+
+        assert!(matches!(decode::<HandshakeReply>(&[7, 0, 0, 0]), Err(DecodeError::Malformed(_))));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(decode::<HandshakeReply>(&trailing).is_err());
+        // string claiming to be 4 GB long
+        assert!(decode::<String>(&[0, 0, 0, 0, 1, 0, 0, 0]).is_err());
+        assert!(matches!(decode::<u8>(&vec![0; MAX_MESSAGE_BYTES + 1]), Err(DecodeError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn text_is_trimmed_and_cut_on_char_boundary() {
+        assert_eq!(clean_text("  hi\u{7}\nthere ", 20), "hithere");
+        assert_eq!(clean_text("žžž", 5), "žž");
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::Resource;
+use bevy_renet::renet::transport::NETCODE_KEY_BYTES;
+use clap::Parser;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::level::DEFAULT_LEVEL;
+use crate::{auth::parse_private_key, DEFAULT_TICK_RATE, PROTOCOL_ID, SKINS};
+
+pub const DEFAULT_PORT: u16 = 5000;
+// netcode panics when a server is made for more clients
+const MAX_CLIENTS: usize = 1024;
+pub const DEFAULT_TOKEN_PORT: u16 = 5001;
+
+// settings of the server, read from a TOML file and then overridden by command line flags
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct ServerSettings {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    // addresses clients connect to, has to contain the LAN / public ip when binding 0.0.0.0
+    pub public_addresses: Vec<SocketAddr>,
+    pub max_clients: usize,
+    pub protocol_id: u64,
+    // skip connect tokens, anybody can connect, for local development
+    pub unsecure: bool,
+    // 64 hex characters, a random one for every start when missing so tokens of an earlier start stop working
+    pub private_key: Option<String>,
+    // tcp port of the token issuer, on the bind address
+    pub token_port: u16,
+    // simulation ticks per second
+    pub tick_rate: f64,
+    // seed of the gameplay rng, random for every match when missing
+    pub seed: Option<u64>,
+    // print snapshot bytes per tick every few seconds
+    pub net_stats: bool,
+    // seconds the player of a dropped client waits for it to connect again, 0 removes it right away
+    pub reconnect_grace: f32,
+    // players in a match, clients over it spectate
+    pub max_players: usize,
+    // file in assets/levels without .ron, clients need the same file
+    pub level: String,
+    // every player starts a round with these, a box falling on it takes one
+    pub lives: u32,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        ServerSettings {
+            bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: DEFAULT_PORT,
+            public_addresses: Vec::new(),
+            max_clients: 64,
+            protocol_id: PROTOCOL_ID,
+            unsecure: false,
+            private_key: None,
+            token_port: DEFAULT_TOKEN_PORT,
+            tick_rate: DEFAULT_TICK_RATE,
+            seed: None,
+            net_stats: false,
+            reconnect_grace: 30.0,
+            max_players: SKINS.len(), // one skin each
+            level: DEFAULT_LEVEL.to_string(),
+            lives: 3,
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn bind_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    // the bind address is used when no public address is set
+    pub fn public_addrs(&self) -> Vec<SocketAddr> {
+        if self.public_addresses.is_empty() {
+            vec![self.bind_addr()]
+        } else {
+            self.public_addresses.clone()
+        }
+    }
+
+    pub fn token_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.token_port)
+    }
+
+    // key that signs connect tokens, from_args checks a configured one and makes one when none is
+    pub fn private_key(&self) -> [u8; NETCODE_KEY_BYTES] {
+        let hex = self.private_key.as_deref().expect("private_key is generated in from_args when missing");
+        parse_private_key(hex).expect("private_key is checked in validate")
+    }
+
+    // the token issuer runs in the same process, so nobody else has to know the key
+    pub fn generate_missing_private_key(&mut self) {
+        if self.private_key.is_some() || self.unsecure {
+            return;
+        }
+        let key: [u8; NETCODE_KEY_BYTES] = rand::random();
+        self.private_key = Some(key.iter().map(|byte| format!("{:02x}", byte)).collect());
+        println!("No private_key configured, using a random one for this run");
+    }
+
+    // values the server can not run with, checked after the config file and flags are merged
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_clients == 0 || self.max_clients > MAX_CLIENTS {
+            return Err(format!("max_clients has to be between 1 and {}, got {}", MAX_CLIENTS, self.max_clients));
+        }
+        if let Some(hex) = &self.private_key {
+            parse_private_key(hex).map_err(|e| format!("private_key: {}", e))?;
+        }
+        check_tick_rate(self.tick_rate)?;
+        if self.max_players == 0 {
+            return Err("max_players has to be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn from_args() -> Self {
+        let mut settings = exit_when_invalid(Self::from(ServerArgs::parse()), Self::validate);
+        settings.generate_missing_private_key();
+        settings
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "Stack attack game server")]
+pub struct ServerArgs {
+    /// TOML file with server settings, flags override values from it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Ip address the server socket binds to
+    #[arg(long)]
+    pub bind: Option<IpAddr>,
+    /// Udp port the server listens on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Address clients use to reach the server, can be repeated
+    #[arg(long = "public-address")]
+    pub public_addresses: Vec<SocketAddr>,
+    #[arg(long)]
+    pub max_clients: Option<usize>,
+    #[arg(long)]
+    pub protocol_id: Option<u64>,
+    /// Let clients in without a connect token
+    #[arg(long)]
+    pub unsecure: bool,
+    /// Tcp port of the connect token issuer
+    #[arg(long)]
+    pub token_port: Option<u16>,
+    /// Simulation ticks per second
+    #[arg(long)]
+    pub tick_rate: Option<f64>,
+    /// Seed for box spawning, replays the same match
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Print how many bytes snapshots take per tick
+    #[arg(long)]
+    pub net_stats: bool,
+    /// Seconds a dropped player is kept for its client to come back
+    #[arg(long)]
+    pub reconnect_grace: Option<f32>,
+    /// Players in a match, everybody else who connects spectates
+    #[arg(long)]
+    pub max_players: Option<usize>,
+    /// Level from assets/levels to play on
+    #[arg(long)]
+    pub level: Option<String>,
+    /// Lives of every player in a round
+    #[arg(long)]
+    pub lives: Option<u32>,
+}
+
+impl From<ServerArgs> for ServerSettings {
+    fn from(args: ServerArgs) -> Self {
+        let mut settings: ServerSettings = args.config.as_deref().map(read_config).unwrap_or_default();
+        if let Some(bind) = args.bind {
+            settings.bind_address = bind;
+        }
+        if let Some(port) = args.port {
+            settings.port = port;
+        }
+        if !args.public_addresses.is_empty() {
+            settings.public_addresses = args.public_addresses;
+        }
+        if let Some(max_clients) = args.max_clients {
+            settings.max_clients = max_clients;
+        }
+        if let Some(protocol_id) = args.protocol_id {
+            settings.protocol_id = protocol_id;
+        }
+        if args.unsecure {
+            settings.unsecure = true;
+        }
+        if let Some(token_port) = args.token_port {
+            settings.token_port = token_port;
+        }
+        if let Some(tick_rate) = args.tick_rate {
+            settings.tick_rate = tick_rate;
+        }
+        if args.seed.is_some() {
+            settings.seed = args.seed;
+        }
+        if args.net_stats {
+            settings.net_stats = true;
+        }
+        if let Some(reconnect_grace) = args.reconnect_grace {
+            settings.reconnect_grace = reconnect_grace;
+        }
+        if let Some(max_players) = args.max_players {
+            settings.max_players = max_players;
+        }
+        if let Some(level) = args.level {
+            settings.level = level;
+        }
+        if let Some(lives) = args.lives {
+            settings.lives = lives;
+        }
+        settings
+    }
+}
+
+// settings of the client, same idea as ServerSettings
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Resource)]
+#[serde(default)]
+pub struct ClientSettings {
+    pub server: SocketAddr,
+    pub protocol_id: u64,
+    pub unsecure: bool,
+    pub token_port: u16,
+    pub name: String,
+    // room to join or make, empty lets the server pick a new code
+    pub room: String,
+    // has to match --tick-rate of the server
+    pub tick_rate: f64,
+    // how far in the past snapshots are shown, more hides packet loss but adds lag
+    pub interpolation_delay_ms: u64,
+    // how long to keep moving entities after snapshots stop coming
+    pub max_extrapolation_ms: u64,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        ClientSettings {
+            server: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), DEFAULT_PORT),
+            protocol_id: PROTOCOL_ID,
+            unsecure: false,
+            token_port: DEFAULT_TOKEN_PORT,
+            name: "player".to_string(),
+            room: String::new(),
+            tick_rate: DEFAULT_TICK_RATE,
+            interpolation_delay_ms: 100,
+            max_extrapolation_ms: 250,
+        }
+    }
+}
+
+impl ClientSettings {
+    // local socket, any interface so the client can reach servers on the LAN
+    pub fn bind_addr(&self) -> SocketAddr {
+        match self.server {
+            SocketAddr::V4(_) => SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            SocketAddr::V6(_) => SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+        }
+    }
+
+    // token issuer runs on the same machine as the server
+    pub fn token_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.server.ip(), self.token_port)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        check_tick_rate(self.tick_rate)
+    }
+
+    pub fn from_args() -> Self {
+        exit_when_invalid(Self::from(ClientArgs::parse()), Self::validate)
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(about = "Stack attack game client")]
+pub struct ClientArgs {
+    /// TOML file with client settings, flags override values from it
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Address of the server as ip:port
+    #[arg(long)]
+    pub server: Option<SocketAddr>,
+    /// Only change the port of the server address
+    #[arg(long)]
+    pub server_port: Option<u16>,
+    #[arg(long)]
+    pub protocol_id: Option<u64>,
+    /// Connect without a connect token, server has to run with --unsecure too
+    #[arg(long)]
+    pub unsecure: bool,
+    /// Tcp port of the connect token issuer
+    #[arg(long)]
+    pub token_port: Option<u16>,
+    /// Name shown to the other players
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Code of the room to play in, a new room when nobody uses it
+    #[arg(long)]
+    pub room: Option<String>,
+    /// Simulation ticks per second of the server
+    #[arg(long)]
+    pub tick_rate: Option<f64>,
+    /// How far in the past other entities are drawn, in milliseconds
+    #[arg(long)]
+    pub interpolation_delay: Option<u64>,
+    /// How long entities keep moving without new snapshots, in milliseconds
+    #[arg(long)]
+    pub max_extrapolation: Option<u64>,
+}
+
+impl From<ClientArgs> for ClientSettings {
+    fn from(args: ClientArgs) -> Self {
+        let mut settings: ClientSettings = args.config.as_deref().map(read_config).unwrap_or_default();
+        if let Some(server) = args.server {
+            settings.server = server;
+        }
+        if let Some(port) = args.server_port {
+            settings.server.set_port(port);
+        }
+        if let Some(protocol_id) = args.protocol_id {
+            settings.protocol_id = protocol_id;
+        }
+        if args.unsecure {
+            settings.unsecure = true;
+        }
+        if let Some(token_port) = args.token_port {
+            settings.token_port = token_port;
+        }
+        if let Some(name) = args.name {
+            settings.name = name;
+        }
+        if let Some(room) = args.room {
+            settings.room = room;
+        }
+        if let Some(tick_rate) = args.tick_rate {
+            settings.tick_rate = tick_rate;
+        }
+        if let Some(delay) = args.interpolation_delay {
+            settings.interpolation_delay_ms = delay;
+        }
+        if let Some(max_extrapolation) = args.max_extrapolation {
+            settings.max_extrapolation_ms = max_extrapolation;
+        }
+        settings
+    }
+}
+
+// Time::<Fixed>::from_hz and the server run loop panic on anything else
+fn check_tick_rate(tick_rate: f64) -> Result<(), String> {
+    if tick_rate.is_finite() && tick_rate > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("tick_rate has to be a positive number, got {}", tick_rate))
+    }
+}
+
+fn exit_when_invalid<T>(settings: T, validate: fn(&T) -> Result<(), String>) -> T {
+    if let Err(e) = validate(&settings) {
+        exit_with_error(&format!("invalid settings: {}", e));
+    }
+    settings
+}
+
+// bad config is a startup error, there is nothing to recover to
+fn read_config<T: DeserializeOwned>(path: &Path) -> T {
+    parse_config(path).unwrap_or_else(|e| exit_with_error(&e))
+}
+
+fn parse_config<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("could not read config {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
+
+fn exit_with_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(2);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_keys_fall_back_to_defaults() {
+        let settings: ServerSettings = toml::from_str("port = 6000\nmax_clients = 4").unwrap();
+        assert_eq!(settings.port, 6000);
+        assert_eq!(settings.max_clients, 4);
+        assert_eq!(settings.protocol_id, PROTOCOL_ID);
+        assert_eq!(settings.public_addrs(), vec!["127.0.0.1:6000".parse().unwrap()]);
+    }
+
+    #[test]
+    fn flags_override_config_file() {
+        let path = std::env::temp_dir().join(format!("server_settings_{}.toml", std::process::id()));
+        fs::write(&path, "bind_address = \"0.0.0.0\"\nport = 6000\npublic_addresses = [\"192.168.0.10:6000\"]").unwrap();
+        let args = ServerArgs::try_parse_from([
+            "server", "--config", path.to_str().unwrap(), "--port", "7000", "--max-clients", "2",
+        ]).unwrap();
+        let settings = ServerSettings::from(args);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(settings.bind_addr(), "0.0.0.0:7000".parse().unwrap());
+        assert_eq!(settings.public_addrs(), vec!["192.168.0.10:6000".parse().unwrap()]);
+        assert_eq!(settings.max_clients, 2);
+    }
+
+    #[test]
+    fn settings_the_server_can_not_run_with_are_rejected() {
+        assert!(ServerSettings::default().validate().is_ok());
+        for args in [["server", "--max-clients", "0"], ["server", "--max-clients", "5000"], ["server", "--tick-rate", "0"], ["server", "--tick-rate=-5", "--port=5000"], ["server", "--max-players", "0"]] {
+            let settings = ServerSettings::from(ServerArgs::try_parse_from(args).unwrap());
+            assert!(settings.validate().is_err(), "{:?}", args);
+        }
+        let settings = ServerSettings { private_key: Some("zz".repeat(NETCODE_KEY_BYTES)), ..Default::default() };
+        assert!(settings.validate().unwrap_err().contains("private_key"));
+        let settings = ClientSettings::from(ClientArgs::try_parse_from(["client", "--tick-rate", "0"]).unwrap());
+        assert!(settings.validate().unwrap_err().contains("tick_rate"));
+        assert!(ClientSettings::default().validate().is_ok());
+
+        let path = std::env::temp_dir().join(format!("bad_settings_{}.toml", std::process::id()));
+        assert!(parse_config::<ServerSettings>(&path).unwrap_err().starts_with("could not read config"));
+        fs::write(&path, "port = \"not a number\"").unwrap();
+        let error = parse_config::<ServerSettings>(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        assert!(error.starts_with("invalid config"), "{}", error);
+    }
+
+    #[test]
+    fn missing_private_key_is_random_and_configured_one_stays() {
+        let (mut first, mut second) = (ServerSettings::default(), ServerSettings::default());
+        first.generate_missing_private_key();
+        second.generate_missing_private_key();
+        assert_ne!(first.private_key(), second.private_key());
+
+        let configured = "00".repeat(NETCODE_KEY_BYTES);
+        let mut settings = ServerSettings { private_key: Some(configured.clone()), ..Default::default() };
+        settings.generate_missing_private_key();
+        assert_eq!(settings.private_key, Some(configured));
+        let mut unsecure = ServerSettings { unsecure: true, ..Default::default() };
+        unsecure.generate_missing_private_key();
+        assert_eq!(unsecure.private_key, None);
+    }
+
+    #[test]
+    fn client_server_port_only_changes_port() {
+        let args = ClientArgs::try_parse_from(["client", "--server", "10.0.0.5:5000", "--server-port", "5001"]).unwrap();
+        let settings = ClientSettings::from(args);
+        assert_eq!(settings.server, "10.0.0.5:5001".parse().unwrap());
+        assert_eq!(settings.bind_addr(), "0.0.0.0:0".parse().unwrap());
+    }
+}
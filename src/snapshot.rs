@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::movement::Animation;
+
+// positions go over the network as multiples of this, about a tenth of a pixel on screen
+pub const POSITION_GRID: f32 = 0.125;
+// ticks of history kept on both sides, an ack older than this gets a full snapshot
+pub const SNAPSHOT_HISTORY: usize = 64;
+
+// one player or box, id is Entity::to_bits() of the server entity, a reused index with a new generation is another id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntitySnapshot {
+    pub id: u64,
+    pub x: i16,
+    pub y: i16,
+    pub pose: Pose, // always the default one for boxes
+}
+
+impl EntitySnapshot {
+    pub fn new(entity: Entity, translation: Vec3, pose: Pose) -> Self {
+        EntitySnapshot {
+            id: entity.to_bits(),
+            x: quantize(translation.x),
+            y: quantize(translation.y),
+            pose,
+        }
+    }
+
+    // z is not sent, everything moving is drawn on the same layer
+    pub fn translation(&self, z: f32) -> Vec3 {
+        Vec3::new(dequantize(self.x), dequantize(self.y), z)
+    }
+}
+
+const FLIPPED: u8 = 0b100;
+
+// animation in the low two bits and whether the player looks left in the third, one byte on the wire
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pose(u8);
+
+impl Pose {
+    pub fn new(animation: Animation, flipped: bool) -> Self {
+        Pose(animation as u8 | if flipped { FLIPPED } else { 0 })
+    }
+
+    pub fn animation(self) -> Animation {
+        match self.0 & 0b11 {
+            0 => Animation::Idle,
+            1 => Animation::Running,
+            2 => Animation::Jumping,
+            _ => Animation::Dead,
+        }
+    }
+
+    pub fn flipped(self) -> bool {
+        self.0 & FLIPPED != 0
+    }
+}
+
+pub fn quantize(value: f32) -> i16 {
+    (value / POSITION_GRID).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+pub fn dequantize(value: i16) -> f32 {
+    value as f32 * POSITION_GRID
+}
+
+pub type WorldState = BTreeMap<u64, EntitySnapshot>;
+
+// what the server sends every tick: entities that differ from base_tick, which the client already has
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct WorldSnapshot {
+    pub tick: u64, // server simulation tick this snapshot was taken in
+    pub base_tick: Option<u64>, // None means everything is in entities
+    pub entities: Vec<EntitySnapshot>,
+    pub removed: Vec<u64>, // were in the base, gone now
+}
+
+impl WorldSnapshot {
+    pub fn encode(tick: u64, current: &WorldState, base: Option<(u64, &WorldState)>) -> Self {
+        let Some((base_tick, base)) = base else {
+            return WorldSnapshot {
+                tick,
+                base_tick: None,
+                entities: current.values().copied().collect(),
+                removed: Vec::new(),
+            };
+        };
+        WorldSnapshot {
+            tick,
+            base_tick: Some(base_tick),
+            entities: current.values().filter(|entity| base.get(&entity.id) != Some(entity)).copied().collect(),
+            removed: base.keys().filter(|id| !current.contains_key(id)).copied().collect(),
+        }
+    }
+
+    // full world state again, None when the base is unknown
+    pub fn decode(&self, history: &SnapshotHistory) -> Option<WorldState> {
+        let mut state = match self.base_tick {
+            Some(base_tick) => history.get(base_tick)?.clone(),
+            None => WorldState::new(),
+        };
+        for id in self.removed.iter() {
+            state.remove(id);
+        }
+        for entity in self.entities.iter() {
+            state.insert(entity.id, *entity);
+        }
+        Some(state)
+    }
+}
+
+// last world states by tick, server keeps what it sent and client what it decoded
+#[derive(Debug, Default)]
+pub struct SnapshotHistory {
+    states: VecDeque<(u64, WorldState)>,
+}
+
+impl SnapshotHistory {
+    pub fn push(&mut self, tick: u64, state: WorldState) {
+        if self.states.back().is_some_and(|(last, _)| tick <= *last) {
+            return;
+        }
+        self.states.push_back((tick, state));
+        if self.states.len() > SNAPSHOT_HISTORY {
+            self.states.pop_front();
+        }
+    }
+
+    pub fn get(&self, tick: u64) -> Option<&WorldState> {
+        self.states.iter().find(|(t, _)| *t == tick).map(|(_, state)| state)
+    }
+
+    pub fn latest_tick(&self) -> Option<u64> {
+        self.states.back().map(|(tick, _)| *tick)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn state(entities: &[(u64, f32)]) -> WorldState {
+        entities
+            .iter()
+            .map(|(id, x)| (*id, EntitySnapshot { id: *id, x: quantize(*x), y: 0, pose: Pose::default() }))
+            .collect()
+    }
+
+    #[test]
+    fn delta_has_only_changes() {
+        let base = state(&[(1, 10.0), (2, 20.0), (3, 30.0)]);
+        let current = state(&[(1, 10.0), (2, 25.0), (4, 40.0)]);
+        let snapshot = WorldSnapshot::encode(11, &current, Some((10, &base)));
+        assert_eq!(snapshot.entities.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 4]);
+        assert_eq!(snapshot.removed, vec![3]);
+
+        let mut history = SnapshotHistory::default();
+        assert_eq!(snapshot.decode(&history), None);
+        history.push(10, base);
+        assert_eq!(snapshot.decode(&history), Some(current));
+    }
+
+    #[test]
+    fn entity_on_a_reused_index_is_a_new_entity() {
+        let mut world = World::new();
+        let old = world.spawn_empty().id();
+        world.despawn(old);
+        let new = world.spawn_empty().id();
+        assert_eq!(old.index(), new.index());
+
+        let base: WorldState = [(old.to_bits(), EntitySnapshot::new(old, Vec3::ZERO, Default::default()))].into();
+        let current: WorldState = [(new.to_bits(), EntitySnapshot::new(new, Vec3::X, Default::default()))].into();
+        let snapshot = WorldSnapshot::encode(11, &current, Some((10, &base)));
+        assert_eq!(snapshot.removed, vec![old.to_bits()]);
+
+        // the client still shows the old box until its despawn arrives, the position is for the new one
+        let mut history = SnapshotHistory::default();
+        history.push(10, base);
+        let client_mapping = HashMap::from([(old, Entity::from_raw(100)), (new, Entity::from_raw(101))]);
+        let moved: Vec<Entity> = snapshot.decode(&history).unwrap().values()
+            .filter_map(|entity| client_mapping.get(&Entity::from_bits(entity.id)).copied())
+            .collect();
+        assert_eq!(moved, vec![Entity::from_raw(101)]);
+    }
+
+    #[test]
+    fn positions_snap_to_grid() {
+        let pose = Pose::new(Animation::Jumping, true);
+        let entity = EntitySnapshot::new(Entity::from_raw(7), Vec3::new(100.06, -12.3, 2.0), pose);
+        assert_eq!(entity.translation(2.0), Vec3::new(100.0, -12.25, 2.0));
+        assert_eq!(quantize(1.0e6), i16::MAX);
+        assert_eq!((pose.animation(), pose.flipped()), (Animation::Jumping, true));
+        assert_eq!(bincode::serialize(&pose).unwrap().len(), 1);
+    }
+}